@@ -1,4 +1,4 @@
-use cosmic_freedesktop_icons::lookup;
+use cosmic_freedesktop_icons::{IconThemeRegistry, lookup};
 use criterion::{
     AxisScale, BenchmarkId, Criterion, PlotConfiguration, criterion_group, criterion_main,
 };
@@ -33,6 +33,24 @@ pub fn bench_lookups(c: &mut Criterion) {
         );
     }
 
+    let registry = IconThemeRegistry::builder().with_file_index().build();
+    for arg in args {
+        group.bench_with_input(
+            BenchmarkId::new("freedesktop-icons-file-index", arg),
+            arg,
+            |b, arg| {
+                b.iter(|| {
+                    lookup(black_box(arg))
+                        .with_scale(black_box(1))
+                        .with_size(black_box(24))
+                        .with_theme(black_box("Adwaita"))
+                        .with_registry(&registry)
+                        .find()
+                });
+            },
+        );
+    }
+
     group.finish();
 }
 
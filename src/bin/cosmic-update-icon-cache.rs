@@ -0,0 +1,21 @@
+//! Write the `icon-theme.cache` of the given theme directories, like `gtk-update-icon-cache`.
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let theme_dirs = std::env::args_os().skip(1).collect::<Vec<_>>();
+    if theme_dirs.is_empty() {
+        eprintln!("usage: cosmic-update-icon-cache THEME_DIR...");
+        return ExitCode::FAILURE;
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    for theme_dir in theme_dirs {
+        if let Err(error) = cosmic_freedesktop_icons::write_icon_theme_cache(&theme_dir) {
+            eprintln!("{}: {error}", theme_dir.to_string_lossy());
+            status = ExitCode::FAILURE;
+        }
+    }
+
+    status
+}
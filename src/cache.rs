@@ -1,75 +1,331 @@
+use crate::{Context, IconInfo, IconStyle, TextDirection};
 use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
-use std::sync::{LazyLock, RwLock};
-use std::time::Instant;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-pub(crate) static CACHE: LazyLock<Cache> = LazyLock::new(Cache::default);
 type Theme = Box<str>;
 type Icon = Box<str>;
-type SizedMap = BTreeMap<(u16, u16), CacheEntry>;
+type Key = (u16, u16, Variant);
+type SizedMap = BTreeMap<Key, Cached>;
 type IconMap = BTreeMap<Icon, SizedMap>;
 type ThemeMap = BTreeMap<Theme, IconMap>;
 
-#[derive(Default)]
-pub(crate) struct Cache(RwLock<ThemeMap>);
+/// A cache of the results of lookups made [`with_cache`](crate::LookupBuilder::with_cache).
+///
+/// Each [`IconThemeRegistry`](crate::IconThemeRegistry) has its own cache, which is unbounded
+/// and cleared when the registry is reloaded. Applications needing another policy can own a
+/// cache and give it to their lookups with
+/// [`with_icon_cache`](crate::LookupBuilder::with_icon_cache).
+///
+/// Whatever the policy, cached results are looked up again once the icon directories changed,
+/// see [`RegistryBuilder::with_revalidation_interval`](crate::RegistryBuilder::with_revalidation_interval).
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::{lookup, IconCache};
+/// use std::time::Duration;
+///
+/// // Remember the last 500 lookups, and search missing icons again after a minute.
+/// let cache = IconCache::new()
+///     .with_max_entries(500)
+///     .with_negative_ttl(Duration::from_secs(60));
+///
+/// let icon = lookup("firefox").with_icon_cache(&cache).find();
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct IconCache {
+    max_entries: Option<usize>,
+    negative_ttl: Option<Duration>,
+    positive_ttl: Option<Duration>,
+    inner: Mutex<Inner>,
+}
+
+// The options of a lookup changing its result, besides its theme, icon names, size and scale.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Variant {
+    pub(crate) style: IconStyle,
+    pub(crate) direction: TextDirection,
+    pub(crate) context: Option<Context>,
+    pub(crate) force_svg: bool,
+    pub(crate) generic_fallback: bool,
+    pub(crate) extra_paths: Box<[PathBuf]>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    themes: ThemeMap,
+    // The key of each entry, by the tick of its last use.
+    recency: BTreeMap<u64, (Theme, Icon, Key)>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct Cached {
+    entry: CacheEntry,
+    inserted: Instant,
+    used: u64,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum CacheEntry {
-    // We already looked for this and nothing was found, indicates we should not try to perform a lookup.
-    NotFound(Instant),
-    // We have this entry.
-    Found(PathBuf),
+    // We already looked for this and nothing was found, indicates we should not try to perform a lookup
+    // as long as the themes have the same fingerprint.
+    NotFound(u64),
+    // We have this entry, found when the themes had the given fingerprint.
+    Found(IconInfo, u64),
     // We don't know this entry yet, indicate we should perform a lookup.
     Unknown,
 }
 
-impl Cache {
+impl IconCache {
+    /// Create an unbounded cache, whose entries never expire.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most the given number of entries, evicting the least recently used ones.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Search icons which were not found again once the given time elapsed.
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// Search icons which were found again once the given time elapsed.
+    pub fn with_positive_ttl(mut self, ttl: Duration) -> Self {
+        self.positive_ttl = Some(ttl);
+        self
+    }
+
+    /// The number of cached lookups.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().recency.len()
+    }
+
+    /// Whether no lookup is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget all the cached lookups.
     pub fn clear(&self) {
-        self.0.write().unwrap().clear();
+        let mut inner = self.inner.lock().unwrap();
+        inner.themes.clear();
+        inner.recency.clear();
+    }
+
+    /// Forget the cached lookups requesting the given theme.
+    pub fn invalidate_theme(&self, theme: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .retain(|cached_theme, _, _| cached_theme != theme);
     }
 
-    pub fn insert<P: AsRef<Path>>(
+    /// Forget the cached lookups which found no icon.
+    pub fn clear_not_found(&self) {
+        self.inner
+            .lock()
+            .unwrap()
+            .retain(|_, _, entry| !matches!(entry, CacheEntry::NotFound(_)));
+    }
+
+    pub(crate) fn insert(
         &self,
         theme: &str,
         size: u16,
         scale: u16,
+        variant: Variant,
         icon_name: &str,
-        icon_path: &Option<P>,
+        entry: CacheEntry,
     ) {
-        let mut inner = self.0.write().unwrap();
-        let entry = icon_path
-            .as_ref()
-            .map(|path| CacheEntry::Found(path.as_ref().to_path_buf()))
-            .unwrap_or(CacheEntry::NotFound(Instant::now()));
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let Inner {
+            themes,
+            recency,
+            tick,
+        } = &mut *inner;
 
-        inner
+        let key = (size, scale, variant);
+        let cached = Cached {
+            entry,
+            inserted: Instant::now(),
+            used: *tick,
+        };
+        let previous = themes
             .entry(theme.into())
-            .or_insert_with(IconMap::default)
+            .or_default()
             .entry(icon_name.into())
-            .or_insert_with(BTreeMap::default)
-            .insert((size, scale), entry);
+            .or_default()
+            .insert(key.clone(), cached);
+
+        if let Some(previous) = previous {
+            recency.remove(&previous.used);
+        }
+        recency.insert(*tick, (theme.into(), icon_name.into(), key));
+
+        if let Some(max_entries) = self.max_entries {
+            while recency.len() > max_entries {
+                let (_, key) = recency.pop_first().unwrap();
+                remove(themes, &key);
+            }
+        }
     }
 
-    pub fn get(&self, theme: &str, size: u16, scale: u16, icon_name: &str) -> CacheEntry {
-        let inner = self.0.read().unwrap();
+    pub(crate) fn get(
+        &self,
+        theme: &str,
+        size: u16,
+        scale: u16,
+        variant: Variant,
+        icon_name: &str,
+    ) -> CacheEntry {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            themes,
+            recency,
+            tick,
+        } = &mut *inner;
 
-        inner
-            .get(theme)
-            .and_then(|icon_map| icon_map.get(icon_name))
-            .and_then(|icon_map| icon_map.get(&(size, scale)).cloned())
-            .unwrap_or(CacheEntry::Unknown)
+        let Some(cached) = themes
+            .get_mut(theme)
+            .and_then(|icon_map| icon_map.get_mut(icon_name))
+            .and_then(|icon_map| icon_map.get_mut(&(size, scale, variant)))
+        else {
+            return CacheEntry::Unknown;
+        };
+
+        let ttl = match cached.entry {
+            CacheEntry::Found(..) => self.positive_ttl,
+            CacheEntry::NotFound(_) => self.negative_ttl,
+            CacheEntry::Unknown => None,
+        };
+        // Expired entries are removed, so that they do not take the place of live ones.
+        if ttl.is_some_and(|ttl| cached.inserted.elapsed() >= ttl) {
+            if let Some(key) = recency.remove(&cached.used) {
+                remove(themes, &key);
+            }
+            return CacheEntry::Unknown;
+        }
+
+        *tick += 1;
+        if let Some(key) = recency.remove(&cached.used) {
+            recency.insert(*tick, key);
+        }
+        cached.used = *tick;
+
+        cached.entry.clone()
+    }
+
+    // Forget the entries of every icon name matching the predicate, in all themes.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn remove_icons(&self, mut predicate: impl FnMut(&str) -> bool) {
+        self.inner
+            .lock()
+            .unwrap()
+            .retain(|_, icon_name, _| !predicate(icon_name));
     }
+}
+
+impl Inner {
+    fn retain(&mut self, mut keep: impl FnMut(&str, &str, &CacheEntry) -> bool) {
+        let themes = &mut self.themes;
+        self.recency.retain(|_, key| {
+            let (theme, icon_name, sized) = &*key;
+            let kept = themes
+                .get(theme)
+                .and_then(|icon_map| icon_map.get(icon_name))
+                .and_then(|icon_map| icon_map.get(sized))
+                .is_some_and(|cached| keep(theme, icon_name, &cached.entry));
 
-    pub fn reset_none(&self) {
-        let mut inner = self.0.write().unwrap();
-        for (_theme_name, theme) in inner.iter_mut() {
-            for (_, cached_icons) in theme.iter_mut() {
-                for (_, cached_icon) in cached_icons.iter_mut() {
-                    if matches!(cached_icon, CacheEntry::NotFound(_)) {
-                        *cached_icon = CacheEntry::Unknown;
-                    }
-                }
+            if !kept {
+                remove(themes, key);
             }
+            kept
+        });
+    }
+}
+
+// Remove an entry, along with the maps it leaves empty.
+fn remove(themes: &mut ThemeMap, (theme, icon_name, sized): &(Theme, Icon, Key)) {
+    let Some(icon_map) = themes.get_mut(theme) else {
+        return;
+    };
+
+    if let Some(sized_map) = icon_map.get_mut(icon_name) {
+        sized_map.remove(sized);
+        if sized_map.is_empty() {
+            icon_map.remove(icon_name);
         }
     }
+
+    if icon_map.is_empty() {
+        themes.remove(theme);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheEntry, IconCache, Variant};
+    use std::time::Duration;
+
+    fn insert(cache: &IconCache, theme: &str, icon_name: &str) {
+        let variant = Variant::default();
+        cache.insert(theme, 24, 1, variant, icon_name, CacheEntry::NotFound(0));
+    }
+
+    fn get(cache: &IconCache, theme: &str, icon_name: &str) -> CacheEntry {
+        cache.get(theme, 24, 1, Variant::default(), icon_name)
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_entries() {
+        let cache = IconCache::new().with_max_entries(2);
+        insert(&cache, "hicolor", "a");
+        insert(&cache, "hicolor", "b");
+        assert_eq!(get(&cache, "hicolor", "a"), CacheEntry::NotFound(0));
+        insert(&cache, "hicolor", "c");
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(get(&cache, "hicolor", "a"), CacheEntry::NotFound(0));
+        assert_eq!(get(&cache, "hicolor", "b"), CacheEntry::Unknown);
+        assert_eq!(get(&cache, "hicolor", "c"), CacheEntry::NotFound(0));
+    }
+
+    #[test]
+    fn should_expire_entries() {
+        let cache = IconCache::new().with_negative_ttl(Duration::ZERO);
+        insert(&cache, "hicolor", "a");
+        assert_eq!(get(&cache, "hicolor", "a"), CacheEntry::Unknown);
+        assert!(cache.is_empty());
+        assert!(cache.inner.lock().unwrap().themes.is_empty());
+
+        let cache = IconCache::new().with_positive_ttl(Duration::ZERO);
+        insert(&cache, "hicolor", "a");
+        assert_eq!(get(&cache, "hicolor", "a"), CacheEntry::NotFound(0));
+    }
+
+    #[test]
+    fn should_invalidate_themes() {
+        let cache = IconCache::new();
+        insert(&cache, "hicolor", "a");
+        insert(&cache, "Cosmic", "a");
+        insert(&cache, "Cosmic", "b");
+        cache.invalidate_theme("Cosmic");
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(get(&cache, "hicolor", "a"), CacheEntry::NotFound(0));
+        assert_eq!(get(&cache, "Cosmic", "a"), CacheEntry::Unknown);
+
+        cache.clear_not_found();
+        assert!(cache.is_empty());
+    }
 }
@@ -0,0 +1,426 @@
+//! An opt-in cache of resolved lookups, persisted under `$XDG_CACHE_HOME` and shared by processes.
+//!
+//! Each theme chain has its own file, named after the directories of the chain. The file records
+//! the [fingerprint](crate::fingerprint) of the chain, and is ignored as soon as it changes.
+//!
+//! Files are never modified in place. Writers hold a lock file, merge the entries of the current
+//! file with their own and atomically replace it, so that readers can map it without locking.
+//! All the numbers are little-endian:
+//!
+//! ```text
+//! Header:  [u8; 8] magic, u32 format version, u32 entry count, u64 fingerprint
+//! Entries: u32 key offset, u32 key length, u32 value offset, u32 value length, sorted by key
+//! Data:    the keys and the values, an empty value records an icon which was not found
+//! ```
+
+use crate::fingerprint::Fnv;
+use crate::theme::{Context, DirectoryType, Theme};
+use crate::{IconInfo, IconSource, ThemeDirectory};
+use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"FDICONS\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 24;
+const ENTRY_LEN: usize = 16;
+
+// New entries are written at most this often, and when the cache is dropped.
+const WRITE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The persistent caches of the theme chains searched through a registry snapshot.
+pub(crate) struct DiskCache {
+    dir: PathBuf,
+    chains: Mutex<BTreeMap<Vec<PathBuf>, Arc<ChainCache>>>,
+}
+
+/// The persistent cache of the lookups in a theme chain.
+pub(crate) struct ChainCache {
+    path: PathBuf,
+    state: RwLock<State>,
+}
+
+struct State {
+    fingerprint: u64,
+    // The cache file, if it matched the fingerprint.
+    mapped: Option<Mmap>,
+    // The entries which are not written yet.
+    pending: BTreeMap<Vec<u8>, Vec<u8>>,
+    written: Instant,
+}
+
+impl DiskCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            chains: Mutex::default(),
+        }
+    }
+
+    /// The cache of the given theme chain, falling back to icons of the given base paths.
+    pub(crate) fn chain(
+        &self,
+        chain: &[&Theme],
+        base_paths: &[PathBuf],
+        fingerprint: u64,
+    ) -> Arc<ChainCache> {
+        let key = chain.iter().map(|theme| theme.path.0.clone()).collect();
+        self.chains
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(ChainCache::open(&self.dir, chain, base_paths, fingerprint))
+            })
+            .clone()
+    }
+
+    /// Write the new entries of every theme chain.
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let chains = self
+            .chains
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        chains.iter().try_for_each(|chain| chain.flush())
+    }
+}
+
+impl ChainCache {
+    fn open(dir: &Path, chain: &[&Theme], base_paths: &[PathBuf], fingerprint: u64) -> Self {
+        let mut name = Fnv::default();
+        for theme in chain {
+            name.write(theme.path.0.as_os_str().as_bytes());
+        }
+        for path in base_paths {
+            name.write(path.as_os_str().as_bytes());
+        }
+
+        let path = dir.join(format!("{:016x}.cache", name.finish()));
+        Self {
+            state: RwLock::new(State {
+                fingerprint,
+                mapped: map(&path, fingerprint),
+                pending: BTreeMap::new(),
+                written: Instant::now(),
+            }),
+            path,
+        }
+    }
+
+    /// The cached result of a lookup, given the current fingerprint of the theme chain:
+    /// `Some(None)` if the icon was not found.
+    pub(crate) fn get(&self, key: &[u8], fingerprint: u64) -> Option<Option<IconInfo>> {
+        if self.state.read().unwrap().fingerprint != fingerprint {
+            self.invalidate(fingerprint);
+        }
+
+        let state = self.state.read().unwrap();
+        let value = match state.pending.get(key) {
+            Some(value) => value.as_slice(),
+            None => View(state.mapped.as_deref()?).get(key)?,
+        };
+
+        decode(value)
+    }
+
+    /// Record the result of a lookup, which is written later on.
+    pub(crate) fn insert(&self, key: Vec<u8>, icon: &Option<IconInfo>) {
+        let mut state = self.state.write().unwrap();
+        state.pending.insert(key, encode(icon));
+
+        if state.written.elapsed() >= WRITE_INTERVAL
+            && let Err(why) = self.write(&mut state)
+        {
+            tracing::warn!(?why, path = ?self.path, "unable to write the icon lookup cache");
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.write(&mut self.state.write().unwrap())
+    }
+
+    // Forget the cached lookups of the previous state of the themes.
+    fn invalidate(&self, fingerprint: u64) {
+        let mut state = self.state.write().unwrap();
+        if fingerprint != state.fingerprint {
+            tracing::debug!(path = ?self.path, "icon themes changed, invalidating the lookup cache");
+            state.fingerprint = fingerprint;
+            state.mapped = map(&self.path, fingerprint);
+            state.pending.clear();
+        }
+    }
+
+    fn write(&self, state: &mut State) -> io::Result<()> {
+        if state.pending.is_empty() {
+            return Ok(());
+        }
+
+        state.written = Instant::now();
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let lock = File::create(self.path.with_extension("lock"))?;
+        lock.lock()?;
+
+        // Keep the entries written by other processes since the file was mapped.
+        let current = map(&self.path, state.fingerprint);
+        let mut entries = current
+            .as_deref()
+            .map(|data| View(data).entries().collect::<BTreeMap<_, _>>())
+            .unwrap_or_default();
+        for (key, value) in &state.pending {
+            entries.insert(key.as_slice(), value.as_slice());
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serialize(state.fingerprint, &entries))?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        state.mapped = map(&self.path, state.fingerprint);
+        state.pending.clear();
+        Ok(())
+    }
+}
+
+impl Drop for ChainCache {
+    fn drop(&mut self) {
+        if let Err(why) = self.flush() {
+            tracing::warn!(?why, path = ?self.path, "unable to write the icon lookup cache");
+        }
+    }
+}
+
+// Map the cache file, if it is valid and matches the fingerprint.
+fn map(path: &Path, fingerprint: u64) -> Option<Mmap> {
+    let file = File::open(path).ok()?;
+    let data = unsafe { Mmap::map(&file) }.ok()?;
+    View(&data).is_valid(fingerprint).then_some(data)
+}
+
+fn serialize(fingerprint: u64, entries: &BTreeMap<&[u8], &[u8]>) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend(MAGIC);
+    data.extend(VERSION.to_le_bytes());
+    data.extend((entries.len() as u32).to_le_bytes());
+    data.extend(fingerprint.to_le_bytes());
+
+    let mut offset = HEADER_LEN + ENTRY_LEN * entries.len();
+    for (key, value) in entries {
+        for n in [offset, key.len(), offset + key.len(), value.len()] {
+            data.extend((n as u32).to_le_bytes());
+        }
+        offset += key.len() + value.len();
+    }
+
+    for (key, value) in entries {
+        data.extend(*key);
+        data.extend(*value);
+    }
+
+    data
+}
+
+// Bounds-checked accessors to the content of a cache file.
+#[derive(Clone, Copy)]
+struct View<'a>(&'a [u8]);
+
+impl<'a> View<'a> {
+    fn u32(self, offset: usize) -> Option<u32> {
+        let bytes = self.0.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn slice(self, offset: usize) -> Option<&'a [u8]> {
+        let start = self.u32(offset)? as usize;
+        let len = self.u32(offset + 4)? as usize;
+        self.0.get(start..start.checked_add(len)?)
+    }
+
+    fn len(self) -> usize {
+        self.u32(12).unwrap_or(0) as usize
+    }
+
+    fn is_valid(self, fingerprint: u64) -> bool {
+        self.0.get(..8) == Some(MAGIC)
+            && self.u32(8) == Some(VERSION)
+            && self.0.get(16..24) == Some(&fingerprint.to_le_bytes())
+            && self
+                .len()
+                .checked_mul(ENTRY_LEN)
+                .is_some_and(|len| HEADER_LEN + len <= self.0.len())
+    }
+
+    fn entry(self, n: usize) -> Option<(&'a [u8], &'a [u8])> {
+        let offset = HEADER_LEN + ENTRY_LEN * n;
+        Some((self.slice(offset)?, self.slice(offset + 8)?))
+    }
+
+    fn entries(self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        (0..self.len()).filter_map(move |n| self.entry(n))
+    }
+
+    fn get(self, key: &[u8]) -> Option<&'a [u8]> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (middle_key, value) = self.entry(middle)?;
+            match middle_key.cmp(key) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(value),
+            }
+        }
+
+        None
+    }
+}
+
+// The icon found by a lookup: its path and where it was found, or nothing if it was not found.
+fn encode(icon: &Option<IconInfo>) -> Vec<u8> {
+    let mut data = Vec::new();
+    let Some(icon) = icon else {
+        return data;
+    };
+
+    let put_str = |data: &mut Vec<u8>, string: &[u8]| {
+        data.extend((string.len() as u32).to_le_bytes());
+        data.extend(string);
+    };
+
+    match &icon.source {
+        IconSource::Theme(directory) => {
+            data.push(0);
+            put_str(&mut data, icon.path.as_os_str().as_bytes());
+            put_str(&mut data, directory.theme.as_bytes());
+            put_str(&mut data, directory.name.as_bytes());
+            data.extend(directory.size.to_le_bytes());
+            data.extend(directory.scale.to_le_bytes());
+            data.push(match directory.type_ {
+                DirectoryType::Fixed => 0,
+                DirectoryType::Scalable => 1,
+                DirectoryType::Threshold => 2,
+            });
+            if let Some(context) = &directory.context {
+                put_str(&mut data, context.as_str().as_bytes());
+            }
+        }
+        IconSource::ExtraPaths => {
+            data.push(1);
+            put_str(&mut data, icon.path.as_os_str().as_bytes());
+        }
+        IconSource::Pixmaps => {
+            data.push(2);
+            put_str(&mut data, icon.path.as_os_str().as_bytes());
+        }
+    }
+
+    data
+}
+
+fn decode(data: &[u8]) -> Option<Option<IconInfo>> {
+    let Some((&source, data)) = data.split_first() else {
+        return Some(None);
+    };
+
+    let mut reader = Reader(data);
+    let path = PathBuf::from(std::ffi::OsStr::from_bytes(reader.str()?));
+    let source = match source {
+        0 => IconSource::Theme(ThemeDirectory {
+            theme: String::from_utf8(reader.str()?.to_vec()).ok()?,
+            name: String::from_utf8(reader.str()?.to_vec()).ok()?,
+            size: reader.u16()?,
+            scale: reader.u16()?,
+            type_: match reader.take(1)?[0] {
+                0 => DirectoryType::Fixed,
+                1 => DirectoryType::Scalable,
+                _ => DirectoryType::Threshold,
+            },
+            context: reader.str().map(Context::from),
+        }),
+        1 => IconSource::ExtraPaths,
+        2 => IconSource::Pixmaps,
+        _ => return None,
+    };
+
+    IconInfo::new(path, source).map(Some)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<&'a [u8]> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().ok()?);
+        self.take(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChainCache, decode, encode};
+    use crate::temp_dir::TempDir;
+    use crate::{Context, DirectoryType, IconInfo, IconSource, ThemeDirectory};
+
+    fn icon(context: Option<Context>) -> IconInfo {
+        let source = IconSource::Theme(ThemeDirectory {
+            theme: "Adwaita".into(),
+            name: "16x16/legacy".into(),
+            size: 16,
+            scale: 2,
+            type_: DirectoryType::Fixed,
+            context,
+        });
+
+        IconInfo::new("/icons/Adwaita/16x16/legacy/edit-delete.png".into(), source).unwrap()
+    }
+
+    #[test]
+    fn should_encode_icons() {
+        let pixmap = IconInfo::new("/pixmaps/app.svg".into(), IconSource::Pixmaps);
+        for icon in [
+            Some(icon(Some(Context::Other("Legacy".into())))),
+            Some(icon(Some(Context::Actions))),
+            Some(icon(None)),
+            pixmap,
+            None,
+        ] {
+            assert_eq!(decode(&encode(&icon)), Some(icon));
+        }
+
+        assert_eq!(decode(&[0, 1]), None);
+    }
+
+    #[test]
+    fn should_merge_entries_of_writers() {
+        let dir = TempDir::new("disk-cache");
+
+        let first = ChainCache::open(&dir, &[], &[], 0);
+        let second = ChainCache::open(&dir, &[], &[], 0);
+        first.insert(b"a".to_vec(), &Some(icon(None)));
+        second.insert(b"b".to_vec(), &None);
+        first.flush().unwrap();
+        second.flush().unwrap();
+
+        let third = ChainCache::open(&dir, &[], &[], 0);
+        let entries = [third.get(b"a", 0), third.get(b"b", 0), third.get(b"c", 0)];
+        assert_eq!(entries, [Some(Some(icon(None))), Some(None), None]);
+    }
+}
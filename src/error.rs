@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+/// The reason why [`LookupBuilder::try_find`](crate::LookupBuilder::try_find) did not find an icon.
+#[derive(Debug, thiserror::Error)]
+pub enum LookupError {
+    /// No theme directory is searched without an icon name, so none is reported.
+    #[error("no icon name to lookup in the '{theme}' theme")]
+    EmptyName { theme: String },
+
+    #[error("icon theme '{theme}' is not installed in any of {base_paths:?}")]
+    ThemeNotFound {
+        theme: String,
+        /// The base directories which were searched for the theme.
+        base_paths: Vec<PathBuf>,
+    },
+
+    #[error("unable to read the index of the '{theme}' icon theme at {path:?}")]
+    IndexUnreadable {
+        theme: String,
+        /// The path of the `index.theme` file.
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("icon '{name}' not found in the '{theme}' theme and its fallbacks, in {paths:?}")]
+    NotFound {
+        /// The icon name, or the icon names separated by `/` for a [`lookup_any`](crate::lookup_any).
+        name: String,
+        theme: String,
+        /// The directories of the theme and its fallbacks which were searched, in order.
+        paths: Vec<PathBuf>,
+    },
+}
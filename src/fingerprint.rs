@@ -0,0 +1,138 @@
+//! Fingerprints of the directories searched by lookups, telling whether cached lookups are
+//! still valid as recommended by the specification.
+
+use crate::theme::Theme;
+use crate::walk_dir;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Hash the modification times of everything a lookup in the theme chain depends on: the
+/// theme directories, their index and icon directories, the base directories, and the extra
+/// paths of the lookup along with their subdirectories.
+pub(crate) fn fingerprint(
+    chain: &[&Theme],
+    base_paths: &[PathBuf],
+    extra_paths: &[PathBuf],
+) -> u64 {
+    let mut hasher = Fnv::default();
+
+    for theme in chain {
+        add_modified(&mut hasher, &theme.path.0);
+        add_modified(&mut hasher, &theme.index);
+        for directory in theme.icon_directories() {
+            add_modified(&mut hasher, &directory);
+        }
+    }
+
+    for path in base_paths {
+        add_modified(&mut hasher, path);
+    }
+
+    for path in extra_paths {
+        add_tree(&mut hasher, path, 0);
+    }
+
+    hasher.finish()
+}
+
+fn add_modified(hasher: &mut Fnv, path: &Path) {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok());
+
+    hasher.write(path.as_os_str().as_bytes());
+    hasher.write(
+        &modified
+            .map_or(0, |modified| modified.as_nanos())
+            .to_le_bytes(),
+    );
+}
+
+// Add a directory and the subdirectories searched by `walk_dir`.
+fn add_tree(hasher: &mut Fnv, directory: &Path, depth: usize) {
+    add_modified(hasher, directory);
+    if depth + 1 >= walk_dir::MAX_DEPTH {
+        return;
+    }
+
+    let mut subdirectories = std::fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    subdirectories.sort_unstable();
+    for subdirectory in subdirectories {
+        add_tree(hasher, &subdirectory, depth + 1);
+    }
+}
+
+/// The FNV-1a hash, which is stable across processes and versions of Rust.
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::temp_dir::{TempDir, write_theme};
+    use crate::theme::Theme;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn should_change_with_icon_directories() {
+        let root = TempDir::new("fingerprint");
+        write_theme(&root, "Fingerprint");
+        std::fs::create_dir_all(root.join("unlisted")).unwrap();
+
+        let theme = Theme::new(root.to_path_buf(), root.join("index.theme"));
+        let fingerprint = || super::fingerprint(&[&theme], &[], &[]);
+        let touch = |directory: &str| {
+            std::fs::File::open(root.join(directory))
+                .and_then(|dir| dir.set_modified(SystemTime::now() + Duration::from_secs(60)))
+                .unwrap();
+        };
+
+        let initial = fingerprint();
+        touch("unlisted");
+        let unlisted = fingerprint();
+        touch("16x16/apps");
+        let listed = fingerprint();
+
+        assert_eq!(initial, unlisted);
+        assert_ne!(initial, listed);
+    }
+
+    #[test]
+    fn should_change_with_extra_subdirectories() {
+        let root = TempDir::new("fingerprint-extra");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+
+        let extra_paths = [root.to_path_buf()];
+        let fingerprint = || super::fingerprint(&[], &[], &extra_paths);
+
+        let initial = fingerprint();
+        std::fs::File::open(root.join("a/b"))
+            .and_then(|dir| dir.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .unwrap();
+
+        assert_ne!(initial, fingerprint());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::theme::{Context, DirectoryType};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Details about an icon found by [`LookupBuilder::find_info`](crate::LookupBuilder::find_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconInfo {
+    pub path: PathBuf,
+    /// Where the icon was found.
+    pub source: IconSource,
+    pub format: IconFormat,
+    /// Whether the icon is symbolic, like [`StyledIcon::symbolic`](crate::StyledIcon::symbolic).
+    pub symbolic: bool,
+}
+
+/// An icon returned by [`LookupBuilder::find_all`](crate::LookupBuilder::find_all).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconCandidate {
+    pub icon: IconInfo,
+    /// The distance between the requested size and the size of the theme directory,
+    /// as defined by the specification. This is `None` for icons found outside of a theme.
+    pub distance: Option<u16>,
+}
+
+/// Where an icon was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IconSource {
+    /// A directory of an icon theme.
+    Theme(ThemeDirectory),
+    /// One of the paths given to [`LookupBuilder::with_extra_paths`](crate::LookupBuilder::with_extra_paths).
+    ExtraPaths,
+    /// The fallback to the base directories, such as `/usr/share/pixmaps`.
+    Pixmaps,
+}
+
+/// A theme directory, as described by the `index.theme` file of the theme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThemeDirectory {
+    /// The name of the theme directory, such as `Adwaita`.
+    pub theme: String,
+    /// The name of the directory relative to the theme, such as `24x24/apps`.
+    pub name: String,
+    /// The nominal size of the icons in this directory.
+    pub size: u16,
+    pub scale: u16,
+    pub type_: DirectoryType,
+    pub context: Option<Context>,
+}
+
+/// The file format of an icon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IconFormat {
+    Png,
+    Svg,
+    Xpm,
+}
+
+impl IconFormat {
+    /// Get the icon format from the extension of the given path.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(OsStr::to_str)? {
+            "png" => Some(Self::Png),
+            "svg" => Some(Self::Svg),
+            "xpm" => Some(Self::Xpm),
+            _ => None,
+        }
+    }
+}
+
+impl IconInfo {
+    pub(crate) fn new(path: PathBuf, source: IconSource) -> Option<Self> {
+        Some(Self {
+            format: IconFormat::from_path(&path)?,
+            symbolic: is_symbolic_icon(&path),
+            source,
+            path,
+        })
+    }
+}
+
+// Symbolic icons are named `<name>-symbolic`, or `<name>-symbolic.symbolic.png` for
+// pre-rendered symbolic icons.
+fn is_symbolic_icon(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(OsStr::to_str)
+        .is_some_and(|stem| crate::theme::is_symbolic_name(stem) || stem.ends_with(".symbolic"))
+}
@@ -51,23 +51,46 @@
 //!     .find();
 //! # }
 //! ```
-use memmap2::Mmap;
-use theme::BASE_PATHS;
-
-use crate::cache::{CACHE, CacheEntry};
-use crate::theme::{THEMES, Theme, try_build_icon_path};
+//!
+//! The internal cache is unbounded, lookups can use an [`IconCache`] of their own with another
+//! policy instead, see [`LookupBuilder::with_icon_cache`].
+use crate::cache::{CacheEntry, Variant};
+use crate::registry::Themes;
+use crate::theme::{SYMBOLIC_SUFFIX, Theme, is_symbolic_name, try_build_icon_path};
+use std::borrow::Cow;
 use std::ffi::OsStr;
-use std::hash::{Hash, Hasher};
-use std::io::BufRead;
-use std::ops::ControlFlow;
 use std::path::PathBuf;
 
 mod cache;
+mod disk_cache;
+mod error;
+mod fingerprint;
+mod info;
+mod query;
+mod registry;
+mod settings;
+#[cfg(test)]
+mod temp_dir;
 mod theme;
 mod walk_dir;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use cache::IconCache;
+pub use error::LookupError;
+pub use info::{IconCandidate, IconFormat, IconInfo, IconSource, ThemeDirectory};
+pub use query::IconQuery;
+pub use registry::{IconThemeRegistry, RegistryBuilder};
+pub use settings::current_theme;
+pub use theme::{Context, DirectoryType, ThemeInfo, write_icon_theme_cache};
+#[cfg(feature = "watch")]
+pub use watch::{IconThemeChange, IconThemeWatcher, WatchHandle};
 
 /// Return the list of installed themes on the system
 ///
+/// Themes without a `Name`, such as the `default` cursor theme which only inherits another
+/// theme, are not listed. See [`themes`] to list all the themes, along with their metadata.
+///
 /// ## Example
 /// ```rust,no_run
 /// # fn main() {
@@ -81,45 +104,123 @@ mod walk_dir;
 /// ])
 /// # }
 pub fn list_themes() -> Vec<String> {
-    let mut themes = THEMES
-        .values()
-        .flatten()
-        .map(|path| &path.index)
-        .filter_map(|index| {
-            let file = std::fs::File::open(index)
-                .and_then(|file| unsafe { Mmap::map(&file) })
-                .ok()?;
-            let mut reader = std::io::Cursor::new(file.as_ref());
-
-            let mut line = String::new();
-            while let Ok(read) = reader.read_line(&mut line) {
-                if read == 0 {
-                    break;
-                }
-
-                if let Some(name) = line.strip_prefix("Name=") {
-                    return Some(name.trim().to_owned());
-                }
+    IconThemeRegistry::global().theme_names()
+}
 
-                line.clear();
-            }
+/// Return the metadata of the installed themes on the system, sorted by theme directory name.
+///
+/// Themes without a `Name` are named after their directory.
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::themes;
+///
+/// for theme in themes().into_iter().filter(|theme| !theme.hidden) {
+///     println!("{} ({}): inherits {:?}", theme.name, theme.id, theme.inherits);
+/// }
+/// # }
+pub fn themes() -> Vec<ThemeInfo> {
+    IconThemeRegistry::global().themes()
+}
 
-            None
-        })
-        .collect::<Vec<_>>();
-    themes.dedup();
-    themes
+/// Return the names of the themes searched by a lookup in the given theme, in order.
+///
+/// This is the given theme followed by the themes it inherits from, resolved depth-first,
+/// and by the fallback themes. If the given theme is not installed, the chain starts with
+/// the `hicolor` theme.
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::theme_chain;
+///
+/// // For instance: ["Papirus-Dark", "Papirus", "breeze", "Cosmic", "hicolor"]
+/// println!("{:?}", theme_chain("Papirus-Dark"));
+/// # }
+pub fn theme_chain(theme: &str) -> Vec<String> {
+    IconThemeRegistry::global().theme_chain(theme)
 }
 
 /// The lookup builder struct, holding all the lookup query parameters.
 pub struct LookupBuilder<'a> {
     name: &'a str,
+    other_names: Names<'a>,
     cache: bool,
     force_svg: bool,
     scale: u16,
     size: u16,
     theme: &'a str,
     extra_paths: &'a [PathBuf],
+    generic_fallback: bool,
+    style: IconStyle,
+    direction: TextDirection,
+    context: Option<Context>,
+    // The global registry is only initialized when used.
+    registry: Option<&'a IconThemeRegistry>,
+    icon_cache: Option<&'a IconCache>,
+}
+
+/// The icon variant preferred by a lookup, see [`LookupBuilder::with_style`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IconStyle {
+    /// Search for the icon name as given.
+    #[default]
+    Any,
+    /// Prefer the `<name>-symbolic` variant and fallback to the full-color icon.
+    Symbolic,
+    /// Prefer the full-color icon and fallback to the `<name>-symbolic` variant.
+    FullColor,
+}
+
+/// The text direction of the user interface, see [`LookupBuilder::with_direction`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextDirection {
+    /// Do not search for directional icon variants.
+    #[default]
+    None,
+    /// Left-to-right, prefer the `<name>-ltr` icon variants.
+    Ltr,
+    /// Right-to-left, prefer the `<name>-rtl` icon variants.
+    Rtl,
+}
+
+/// An icon found by [`LookupBuilder::find_styled`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyledIcon {
+    pub path: PathBuf,
+    /// Whether the icon is a symbolic variant, which is expected to be recolored by the caller.
+    pub symbolic: bool,
+}
+
+// The alternative icon names of a lookup, borrowed from a `lookup_any` or an `IconQuery`.
+#[derive(Clone, Copy)]
+enum Names<'a> {
+    Borrowed(&'a [&'a str]),
+    Owned(&'a [String]),
+}
+
+impl<'a> Names<'a> {
+    fn iter(self) -> impl Iterator<Item = &'a str> {
+        let (borrowed, owned) = match self {
+            Names::Borrowed(names) => (names, &[][..]),
+            Names::Owned(names) => (&[][..], names),
+        };
+
+        borrowed
+            .iter()
+            .copied()
+            .chain(owned.iter().map(String::as_str))
+    }
+
+    fn is_empty(self) -> bool {
+        match self {
+            Names::Borrowed(names) => names.is_empty(),
+            Names::Owned(names) => names.is_empty(),
+        }
+    }
 }
 
 /// Build an icon lookup for the given icon name.
@@ -135,6 +236,30 @@ pub fn lookup(name: &str) -> LookupBuilder<'_> {
     LookupBuilder::new(name)
 }
 
+/// Build an icon lookup for the first icon found among the given icon names.
+///
+/// The names are given in order of preference, and are all searched in a theme
+/// before moving on to its parent themes. This is the behavior expected for the
+/// list of names of a desktop entry or a themed icon, and differs from looking up
+/// each name one after the other.
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::lookup_any;
+///
+/// let icon = lookup_any(&["org.mozilla.firefox", "firefox", "web-browser"]).find();
+/// # }
+pub fn lookup_any<'a>(names: &'a [&'a str]) -> LookupBuilder<'a> {
+    match names.split_first() {
+        Some((name, other_names)) => LookupBuilder {
+            other_names: Names::Borrowed(other_names),
+            ..LookupBuilder::new(name)
+        },
+        None => LookupBuilder::new(""),
+    }
+}
+
 impl<'a> LookupBuilder<'a> {
     /// Restrict the lookup to the given icon size.
     ///
@@ -191,6 +316,9 @@ impl<'a> LookupBuilder<'a> {
     /// This can drastically increase lookup performances for application
     /// that repeat the same lookups, an application launcher for instance.
     ///
+    /// Cached results are looked up again once the icon directories changed, see
+    /// [`RegistryBuilder::with_revalidation_interval`].
+    ///
     /// ## Example
     /// ```rust
     /// # fn main() {
@@ -234,242 +362,752 @@ impl<'a> LookupBuilder<'a> {
         self
     }
 
+    /// Do not retry failed lookups with more generic icon names.
+    ///
+    /// By default, a lookup for `network-wireless-signal-good` which is not found
+    /// in any theme is retried with `network-wireless-signal`, then `network-wireless`,
+    /// and finally `network`, as recommended by the icon naming specification.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::lookup;
+    ///
+    /// let icon = lookup("network-wireless-signal-good")
+    ///     .without_generic_fallback()
+    ///     .find();
+    /// # }
+    #[inline]
+    pub fn without_generic_fallback(mut self) -> Self {
+        self.generic_fallback = false;
+        self
+    }
+
+    /// Prefer the symbolic or the full-color variant of the icon.
+    ///
+    /// The icon name may be given with or without its `-symbolic` suffix.
+    /// With [`IconStyle::Symbolic`], `<name>-symbolic` is searched first, preferably
+    /// as an Svg from the `symbolic` and scalable directories of the theme, and the
+    /// full-color icon is used as a fallback. [`IconStyle::FullColor`] does the reverse.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, IconStyle};
+    ///
+    /// let icon = lookup("edit-delete")
+    ///     .with_style(IconStyle::Symbolic)
+    ///     .find_styled();
+    /// # }
+    #[inline]
+    pub fn with_style(mut self, style: IconStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Prefer the icon variants matching the given text direction.
+    ///
+    /// In each theme, `<name>-rtl` (or `<name>-ltr`) is searched before `<name>`,
+    /// so that icons such as `go-next` or `edit-undo` point in the right direction.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, TextDirection};
+    ///
+    /// let icon = lookup("go-next")
+    ///     .with_direction(TextDirection::Rtl)
+    ///     .find();
+    /// # }
+    #[inline]
+    pub fn with_direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Prefer the icons of the given context, such as [`Context::Places`] or [`Context::Devices`].
+    ///
+    /// Icons with the same name may exist in several contexts. In each theme, the directories
+    /// of the requested context are searched first, and then the directories of any context.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, Context};
+    ///
+    /// let icon = lookup("computer")
+    ///     .with_context(Context::Places)
+    ///     .find();
+    /// # }
+    #[inline]
+    pub fn with_context(mut self, context: Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Search the themes of the given registry instead of the global one.
+    ///
+    /// Cached lookups use the cache of the given registry, which is cleared
+    /// when the registry is reloaded.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, IconThemeRegistry};
+    ///
+    /// let registry = IconThemeRegistry::new();
+    /// let icon = lookup("firefox")
+    ///     .with_registry(&registry)
+    ///     .find();
+    /// # }
+    #[inline]
+    pub fn with_registry<'b: 'a>(mut self, registry: &'b IconThemeRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Cache the result of the lookup in the given cache, instead of the cache of the registry.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, IconCache};
+    ///
+    /// let cache = IconCache::new().with_max_entries(100);
+    /// let icon = lookup("firefox")
+    ///     .with_icon_cache(&cache)
+    ///     .find();
+    /// # }
+    #[inline]
+    pub fn with_icon_cache<'b: 'a>(mut self, cache: &'b IconCache) -> Self {
+        self.cache = true;
+        self.icon_cache = Some(cache);
+        self
+    }
+
     /// Execute the current lookup
     /// if no icon is found in the current theme fallback to
     /// `/usr/share/icons/hicolor` theme and then to `/usr/share/pixmaps`.
+    /// If the icon is still not found, the lookup is repeated with
+    /// dash-separated suffixes stripped from the icon name.
     #[inline]
     pub fn find(self) -> Option<PathBuf> {
-        if self.name.is_empty() {
-            return None;
+        self.find_info().map(|icon| icon.path)
+    }
+
+    /// Execute the current lookup like [`find`], reporting why no icon was found.
+    ///
+    /// Like [`find`], the lookup falls back to the `hicolor` theme when the requested
+    /// theme is not installed, [`LookupError::ThemeNotFound`] is only returned if the icon
+    /// could not be found at all.
+    ///
+    /// [`find`]: LookupBuilder::find
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, LookupError};
+    ///
+    /// match lookup("firefox").with_theme("Papirus").try_find() {
+    ///     Ok(path) => println!("found {}", path.display()),
+    ///     Err(LookupError::ThemeNotFound { theme, .. }) => eprintln!("{theme} is not installed"),
+    ///     Err(why) => eprintln!("{why}"),
+    /// }
+    /// # }
+    pub fn try_find(self) -> Result<PathBuf, LookupError> {
+        if self.names().next().is_none() {
+            return Err(LookupError::EmptyName {
+                theme: self.theme.to_owned(),
+            });
         }
 
+        match self.lookup_in_theme() {
+            Some(icon) => Ok(icon.path),
+            None => Err(self.lookup_error()),
+        }
+    }
+
+    /// Execute the current lookup, returning the details of the icon found.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, IconSource};
+    ///
+    /// if let Some(icon) = lookup("firefox").with_size(48).find_info() {
+    ///     if let IconSource::Theme(directory) = icon.source {
+    ///         println!("{} found in {}/{}", icon.path.display(), directory.theme, directory.name);
+    ///     }
+    /// }
+    /// # }
+    pub fn find_info(self) -> Option<IconInfo> {
+        // There is nothing to lookup without a non-empty icon name
+        self.names().next()?;
+
         // Lookup for an icon in the given theme and fallback to 'hicolor' default theme
         self.lookup_in_theme()
     }
 
+    /// Execute the current lookup, reporting whether a symbolic variant of the icon was found.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, IconStyle};
+    ///
+    /// if let Some(icon) = lookup("edit-delete").with_style(IconStyle::Symbolic).find_styled() {
+    ///     println!("{} (symbolic: {})", icon.path.display(), icon.symbolic);
+    /// }
+    /// # }
+    #[inline]
+    pub fn find_styled(self) -> Option<StyledIcon> {
+        self.find_info().map(|icon| StyledIcon {
+            path: icon.path,
+            symbolic: icon.symbolic,
+        })
+    }
+
+    /// Get every icon file matching the current lookup, in the order [`find`] would rank them.
+    ///
+    /// This includes the icons found in each theme of the theme chain, in each matching
+    /// directory of those themes and with each supported file extension. The cache is
+    /// not used for this lookup.
+    ///
+    /// [`find`]: LookupBuilder::find
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::lookup;
+    ///
+    /// for candidate in lookup("firefox").with_size(48).find_all() {
+    ///     println!("{} ({:?})", candidate.icon.path.display(), candidate.distance);
+    /// }
+    /// # }
+    pub fn find_all(self) -> impl Iterator<Item = IconCandidate> {
+        let mut candidates = Vec::new();
+        if self.names().next().is_none() {
+            return candidates.into_iter();
+        }
+
+        let themes = self.registry().snapshot();
+        let chain = self.theme_chain(&themes);
+        for names in self.candidate_names() {
+            let names = names
+                .iter()
+                .flat_map(|name| self.directional_names(name))
+                .collect::<Vec<_>>();
+
+            let flat_candidate = |icon| IconCandidate {
+                icon,
+                distance: None,
+            };
+
+            for name in &names {
+                candidates.extend(self.extra_path_icons(name).map(flat_candidate));
+            }
+
+            for theme in &chain {
+                for name in &names {
+                    let icons = theme.get_all_icons(
+                        name,
+                        self.size,
+                        self.scale,
+                        self.prefer_svg(name),
+                        self.context.as_ref(),
+                    );
+                    candidates.extend(icons.into_iter().map(|(icon, distance)| IconCandidate {
+                        icon,
+                        distance: Some(distance),
+                    }));
+                }
+            }
+
+            for name in &names {
+                candidates.extend(
+                    self.base_path_icons(&themes.base_paths, name)
+                        .map(flat_candidate),
+                );
+            }
+        }
+
+        candidates.into_iter()
+    }
+
+    // Diagnose why the lookup failed.
+    fn lookup_error(&self) -> LookupError {
+        let themes = self.registry().snapshot();
+        if !themes.themes.contains_key(self.theme.as_bytes()) {
+            return LookupError::ThemeNotFound {
+                theme: self.theme.to_owned(),
+                base_paths: themes.base_paths.clone(),
+            };
+        }
+
+        let chain = self.theme_chain(&themes);
+        for theme in &chain {
+            if let Err(source) = theme::read_ini_theme(&theme.index) {
+                return LookupError::IndexUnreadable {
+                    theme: theme.name().into_owned(),
+                    path: theme.index.clone(),
+                    source,
+                };
+            }
+        }
+
+        LookupError::NotFound {
+            name: self.cache_name().into_owned(),
+            theme: self.theme.to_owned(),
+            paths: chain.iter().map(|theme| theme.path.0.clone()).collect(),
+        }
+    }
+
     fn new<'b: 'a>(name: &'b str) -> Self {
         Self {
             name,
+            other_names: Names::Borrowed(&[]),
             cache: false,
             force_svg: false,
             scale: 1,
             size: 24,
             theme: "hicolor",
             extra_paths: &[],
+            generic_fallback: true,
+            style: IconStyle::Any,
+            direction: TextDirection::None,
+            context: None,
+            registry: None,
+            icon_cache: None,
         }
     }
 
     // Recursively lookup for icon in the given theme and its parents
-    fn lookup_in_theme(&self) -> Option<PathBuf> {
-        // If cache is activated, attempt to get the icon there first
-        // If the icon was previously search but not found, we return
+    fn lookup_in_theme(&self) -> Option<IconInfo> {
+        let themes = self.registry().snapshot();
+
+        // If cache is activated, attempt to get the icon there first, as long as the themes
+        // did not change since. If the icon was previously searched but not found, we return
         // `None` early, otherwise, attempt to perform a lookup
-        if self.cache {
+        let fingerprint = self.cache.then(|| {
+            themes.fingerprint(
+                self.theme,
+                self.extra_paths,
+                self.registry().builder.revalidation_interval,
+                || self.theme_chain(&themes),
+            )
+        });
+        if let Some(fingerprint) = fingerprint {
             match self.cache_lookup(self.theme) {
-                CacheEntry::Found(icon) => return Some(icon),
-                CacheEntry::NotFound(last_check) if last_check.elapsed().as_secs() < 5 => {
-                    return None;
+                // The file may have been removed since the themes were last checked.
+                CacheEntry::Found(icon, found) if found == fingerprint && icon.path.exists() => {
+                    return Some(icon);
                 }
+                CacheEntry::NotFound(found) if found == fingerprint => return None,
                 _ => (),
             }
         }
 
-        if !self.extra_paths.is_empty() {
-            let mut svg_path = None;
-            let mut png_path = None;
-            let mut xpm_path = None;
+        let chain = self.theme_chain(&themes);
+
+        // The lookup may have been resolved by another process, unless it searches extra paths.
+        let disk_cache = themes
+            .disk_cache
+            .as_ref()
+            .zip(fingerprint)
+            .filter(|_| self.extra_paths.is_empty())
+            .map(|(disk_cache, fingerprint)| {
+                (
+                    disk_cache.chain(&chain, &themes.base_paths, fingerprint),
+                    self.disk_cache_key(),
+                    fingerprint,
+                )
+            });
+        if let Some((chain_cache, key, fingerprint)) = &disk_cache
+            && let Some(icon) = chain_cache.get(key, *fingerprint)
+            && icon.as_ref().is_none_or(|icon| icon.path.exists())
+        {
+            return self.store(self.theme, icon, *fingerprint);
+        }
 
-            for file_path in walk_dir::Iter::new(self.extra_paths.iter().cloned()) {
-                if let Some(file_name) = file_path.file_stem().and_then(OsStr::to_str)
-                    && file_name != self.name
-                {
-                    continue;
-                }
+        let icon = self
+            .candidate_names()
+            .iter()
+            .find_map(|names| self.lookup_names(&themes, &chain, names));
 
-                if let Some(this_ext) = file_path.extension().and_then(OsStr::to_str) {
-                    match this_ext {
-                        "svg" => {
-                            svg_path = Some(file_path);
-                            if self.force_svg || png_path.is_some() {
-                                break;
-                            }
-                        }
-
-                        "png" => {
-                            png_path = Some(file_path);
-                            if !self.force_svg || svg_path.is_some() {
-                                break;
-                            }
-                        }
-
-                        "xpm" => {
-                            xpm_path = Some(file_path);
-                        }
-
-                        _ => (),
-                    }
-                }
-            }
+        if let Some((chain_cache, key, _)) = disk_cache {
+            chain_cache.insert(key, &icon);
+        }
 
-            if let Some(path) = if self.force_svg {
-                svg_path.or(png_path).or(xpm_path)
-            } else {
-                png_path.or(svg_path).or(xpm_path)
-            } {
-                return Some(path);
-            }
+        match fingerprint {
+            Some(fingerprint) => self.store(self.theme, icon, fingerprint),
+            None => icon,
         }
+    }
+
+    // Lists of icon names to search for, in order of preference. All the names of
+    // a list are searched in a theme before moving on to its parents, and each list
+    // is searched through the whole theme chain before moving on to the next one.
+    fn candidate_names(&self) -> Vec<Vec<Cow<'a, str>>> {
+        let names = self.names().map(|name| match self.style {
+            IconStyle::Any => name,
+            IconStyle::Symbolic | IconStyle::FullColor => {
+                name.strip_suffix(SYMBOLIC_SUFFIX).unwrap_or(name)
+            }
+        });
+
+        // Strip one more suffix from every name on each generic fallback level.
+        let levels = if self.generic_fallback { usize::MAX } else { 1 };
+        let mut generic = names
+            .map(|name| generic_names(name).take(levels))
+            .collect::<Vec<_>>();
+        let mut searched = Vec::new();
+        let generic_levels = std::iter::from_fn(|| {
+            let level = generic
+                .iter_mut()
+                .filter_map(Iterator::next)
+                .filter(|name| {
+                    let new = !searched.contains(name);
+                    searched.push(name.clone());
+                    new
+                })
+                .collect::<Vec<_>>();
+            (!level.is_empty()).then_some(level)
+        })
+        .collect::<Vec<_>>();
 
-        // Records theme paths that have already been searched.
-        let searched_themes = &mut Vec::new();
-        // Record themes whose inherits have been searched.
-        let search_inherits = &mut Vec::new();
-
-        // Then lookup in the given theme
-        THEMES
-            .get(self.theme.as_bytes())
-            .or_else(|| THEMES.get("hicolor".as_bytes()))
-            .and_then(|icon_themes| {
-                let icon = icon_themes
+        let regular = || generic_levels.iter().cloned();
+
+        let symbolic = || {
+            generic_levels.iter().map(|names| {
+                names
                     .iter()
-                    // Search the active icon themes
-                    .find_map(|theme| self.search_theme(searched_themes, theme))
-                    // Search the inherits of those icon themes.
-                    .or_else(|| {
-                        icon_themes.iter().find_map(|t| {
-                            self.search_theme_inherits(search_inherits, searched_themes, t)
-                        })
-                    })
-                    // Search the cosmic icon theme
-                    .or_else(|| self.search_inherited_theme(searched_themes, "Cosmic".as_bytes()))
-                    // Search the hicolor icon theme if it was not previously searched
-                    .or_else(|| self.search_inherited_theme(searched_themes, "hicolor".as_bytes()))
-                    // GNOME applications may rely on the gnome theme
-                    .or_else(|| self.search_inherited_theme(searched_themes, "gnome".as_bytes()))
-                    // Ubuntu applications may require Yaru
-                    .or_else(|| self.search_inherited_theme(searched_themes, "Yaru".as_bytes()))
-                    .or_else(|| {
-                        let extensions = if self.force_svg {
-                            [".svg", ".png", ".xpm"]
-                        } else {
-                            [".png", ".svg", ".xpm"]
-                        };
-
-                        let mut name_buf = String::new();
-
-                        extensions
-                            .into_iter()
-                            .try_for_each(|ext| {
-                                BASE_PATHS.iter().try_for_each(|theme_base_dir| {
-                                    let mut path = theme_base_dir.clone();
-                                    if try_build_icon_path(&mut path, &mut name_buf, self.name, ext)
-                                    {
-                                        return ControlFlow::Break(path);
-                                    }
-                                    name_buf.clear();
-                                    ControlFlow::Continue(())
-                                })
-                            })
-                            .break_value()
-                    });
-
-                if self.cache {
-                    self.store(self.theme, icon)
-                } else {
-                    icon
-                }
+                    .map(|name| Cow::Owned([name, SYMBOLIC_SUFFIX].concat()))
+                    .collect()
             })
+        };
+
+        match self.style {
+            IconStyle::Any => regular().collect(),
+            IconStyle::Symbolic => symbolic().chain(regular()).collect(),
+            IconStyle::FullColor => regular().chain(symbolic()).collect(),
+        }
     }
 
-    #[inline]
-    pub fn cache_clear(&mut self) {
-        CACHE.clear();
+    // The non-empty icon names of the lookup, in order of preference.
+    fn names(&self) -> impl Iterator<Item = &'a str> + use<'a> {
+        std::iter::once(self.name)
+            .chain(self.other_names.iter())
+            .filter(|name| !name.is_empty())
     }
 
+    // Svg icons are preferred when forced, or when searching for a symbolic icon in symbolic mode.
     #[inline]
-    pub fn cache_reset_none(&mut self) {
-        CACHE.reset_none();
+    fn prefer_svg(&self, name: &str) -> bool {
+        self.force_svg || (self.style == IconStyle::Symbolic && is_symbolic_name(name))
     }
 
-    #[inline]
-    fn cache_lookup(&self, theme: &str) -> CacheEntry {
-        CACHE.get(theme, self.size, self.scale, self.name)
+    // Lookup for a list of icon names in the extra paths, the theme chain and the pixmaps.
+    fn lookup_names(
+        &self,
+        themes: &Themes,
+        chain: &[&Theme],
+        names: &[Cow<str>],
+    ) -> Option<IconInfo> {
+        let names = names
+            .iter()
+            .flat_map(|name| self.directional_names(name))
+            .collect::<Vec<_>>();
+        names
+            .iter()
+            .find_map(|name| self.extra_path_icons(name).next())
+            .or_else(|| {
+                chain
+                    .iter()
+                    .find_map(|theme| self.search_theme(theme, &names))
+            })
+            .or_else(|| {
+                names
+                    .iter()
+                    .find_map(|name| self.base_path_icons(&themes.base_paths, name).next())
+            })
     }
 
-    #[inline]
-    fn store(&self, theme: &str, icon: Option<PathBuf>) -> Option<PathBuf> {
-        CACHE.insert(theme, self.size, self.scale, self.name, &icon);
-        icon
+    // The directional variants of an icon name, followed by the name itself.
+    fn directional_names<'b>(&self, name: &'b str) -> Vec<Cow<'b, str>> {
+        let suffix = match self.direction {
+            TextDirection::None => return vec![Cow::Borrowed(name)],
+            TextDirection::Ltr => "-ltr",
+            TextDirection::Rtl => "-rtl",
+        };
+
+        match name.strip_suffix(SYMBOLIC_SUFFIX) {
+            // Themes name them either `<name>-symbolic-rtl` or `<name>-rtl-symbolic`.
+            Some(base) => vec![
+                Cow::Owned([name, suffix].concat()),
+                Cow::Owned([base, suffix, SYMBOLIC_SUFFIX].concat()),
+                Cow::Borrowed(name),
+            ],
+            None => vec![Cow::Owned([name, suffix].concat()), Cow::Borrowed(name)],
+        }
     }
 
-    /// Search a theme by its path for a matching icon if not already searched.
-    fn search_theme(&self, searched_themes: &mut Vec<u64>, theme: &Theme) -> Option<PathBuf> {
-        // Store hash of the theme.
-        let theme_hash = {
-            let mut hasher = std::hash::DefaultHasher::new();
-            theme.path.0.hash(&mut hasher);
-            hasher.finish()
+    // Every icon matching the name in the extra paths, with the preferred formats first.
+    fn extra_path_icons(&self, name: &str) -> impl Iterator<Item = IconInfo> + use<> {
+        let preference = if self.prefer_svg(name) {
+            [IconFormat::Svg, IconFormat::Png, IconFormat::Xpm]
+        } else {
+            [IconFormat::Png, IconFormat::Svg, IconFormat::Xpm]
         };
 
-        if let Err(pos) = searched_themes.binary_search(&theme_hash) {
-            searched_themes.insert(pos, theme_hash);
-            return theme.try_get_icon(self.name, self.size, self.scale, self.force_svg);
+        let mut icons = walk_dir::Iter::new(self.extra_paths.iter().cloned())
+            .filter(|path| path.file_stem().and_then(OsStr::to_str) == Some(name))
+            .filter_map(|path| IconInfo::new(path, IconSource::ExtraPaths))
+            .collect::<Vec<_>>();
+        icons.sort_by_key(|icon| preference.iter().position(|format| *format == icon.format));
+        icons.into_iter()
+    }
+
+    // The themes to search in order: the requested theme, its parents and the fallback themes.
+    // A theme may be installed in several base paths, each of them is searched once.
+    fn theme_chain<'t>(&self, themes: &'t Themes) -> Vec<&'t Theme> {
+        let mut chain = Vec::<&Theme>::new();
+        for name in themes.chain_names(self.theme, &self.registry().builder.fallback_themes) {
+            for theme in &themes.themes[name] {
+                if !chain.iter().any(|searched| searched.path.0 == theme.path.0) {
+                    chain.push(theme);
+                }
+            }
         }
 
-        None
+        chain
     }
 
-    // Search the inherits of a theme if not already searched.
-    fn search_theme_inherits(
+    // Search the icon as a flat file of the base paths, such as `/usr/share/pixmaps`.
+    fn base_path_icons<'b>(
         &self,
-        search_inherits: &mut Vec<u64>,
-        searched_themes: &mut Vec<u64>,
-        theme: &Theme,
-    ) -> Option<PathBuf> {
-        // Store hash of the theme.
-        let theme_hash = {
-            let mut hasher = std::hash::DefaultHasher::new();
-            theme.path.0.hash(&mut hasher);
-            hasher.finish()
+        base_paths: &'b [PathBuf],
+        name: &'b str,
+    ) -> impl Iterator<Item = IconInfo> + use<'b> {
+        let extensions = if self.prefer_svg(name) {
+            [".svg", ".png", ".xpm"]
+        } else {
+            [".png", ".svg", ".xpm"]
         };
 
-        if let Err(pos) = search_inherits.binary_search(&theme_hash) {
-            search_inherits.insert(pos, theme_hash);
-            let Ok(file) = theme::read_ini_theme(&theme.index) else {
-                return None;
-            };
+        extensions.into_iter().flat_map(move |ext| {
+            base_paths.iter().filter_map(move |theme_base_dir| {
+                let mut path = theme_base_dir.clone();
+                let mut name_buf = String::new();
+                try_build_icon_path(&mut path, &mut name_buf, name, ext)
+                    .then(|| IconInfo::new(path, IconSource::Pixmaps))
+                    .flatten()
+            })
+        })
+    }
 
-            // Search all inherited themes that we haven't already searched
-            return theme
-                .inherits(file.as_ref())
-                .into_iter()
-                .find_map(|parent| self.search_inherited_theme(searched_themes, parent));
+    #[inline]
+    #[deprecated(
+        note = "use `IconCache::clear` on `IconThemeRegistry::cache` or on your own cache"
+    )]
+    pub fn cache_clear(&self) {
+        self.icon_cache().clear();
+    }
+
+    #[inline]
+    #[deprecated(
+        note = "use `IconCache::clear_not_found` on `IconThemeRegistry::cache` or on your own cache"
+    )]
+    pub fn cache_reset_none(&self) {
+        self.icon_cache().clear_not_found();
+    }
+
+    // The registry given to the lookup, or the global registry.
+    #[inline]
+    fn registry(&self) -> &'a IconThemeRegistry {
+        match self.registry {
+            Some(registry) => registry,
+            None => IconThemeRegistry::global(),
         }
+    }
 
-        None
+    // The cache given to the lookup, or the cache of its registry.
+    #[inline]
+    fn icon_cache(&self) -> &'a IconCache {
+        self.icon_cache.unwrap_or(&self.registry().cache)
     }
 
-    /// Search the inherits of a theme by its name if not already searched.
-    fn search_inherited_theme(
-        &self,
-        searched_themes: &mut Vec<u64>,
-        theme: &[u8],
-    ) -> Option<PathBuf> {
-        THEMES
-            .get(theme)?
-            .iter()
-            .find_map(|t| self.search_theme(searched_themes, t))
+    #[inline]
+    fn variant(&self) -> Variant {
+        Variant {
+            style: self.style,
+            direction: self.direction,
+            context: self.context.clone(),
+            force_svg: self.force_svg,
+            generic_fallback: self.generic_fallback,
+            extra_paths: self.extra_paths.into(),
+        }
+    }
+
+    // Icon names cannot contain a `/`, which makes it suitable to join multiple names.
+    fn cache_name(&self) -> Cow<'a, str> {
+        if self.other_names.is_empty() {
+            Cow::Borrowed(self.name)
+        } else {
+            Cow::Owned(self.names().collect::<Vec<_>>().join("/"))
+        }
+    }
+
+    // The key of the lookup in the persistent cache, covering the options changing its result
+    // within a theme chain.
+    fn disk_cache_key(&self) -> Vec<u8> {
+        let mut key = self.cache_name().as_bytes().to_vec();
+        key.push(0);
+        key.extend(self.size.to_le_bytes());
+        key.extend(self.scale.to_le_bytes());
+        key.extend([
+            self.style as u8,
+            self.direction as u8,
+            self.force_svg as u8,
+            self.generic_fallback as u8,
+        ]);
+        if let Some(context) = &self.context {
+            key.extend(context.as_str().as_bytes());
+        }
+
+        key
+    }
+
+    #[inline]
+    fn cache_lookup(&self, theme: &str) -> CacheEntry {
+        self.icon_cache().get(
+            theme,
+            self.size,
+            self.scale,
+            self.variant(),
+            &self.cache_name(),
+        )
     }
+
+    #[inline]
+    fn store(&self, theme: &str, icon: Option<IconInfo>, fingerprint: u64) -> Option<IconInfo> {
+        self.icon_cache().insert(
+            theme,
+            self.size,
+            self.scale,
+            self.variant(),
+            &self.cache_name(),
+            match &icon {
+                Some(icon) => CacheEntry::Found(icon.clone(), fingerprint),
+                None => CacheEntry::NotFound(fingerprint),
+            },
+        );
+        icon
+    }
+
+    /// Search a theme for the first matching icon name.
+    fn search_theme(&self, theme: &Theme, names: &[Cow<str>]) -> Option<IconInfo> {
+        names.iter().find_map(|name| {
+            theme.try_get_icon(
+                name,
+                self.size,
+                self.scale,
+                self.prefer_svg(name),
+                self.context.as_ref(),
+            )
+        })
+    }
+}
+
+/// Yields the icon name followed by its generic fallbacks, obtained by
+/// stripping dash-separated suffixes one at a time. Like GTK, the `-symbolic`
+/// and directional suffixes are kept: `edit-delete-foo-symbolic` falls back
+/// to `edit-delete-symbolic` rather than to `edit-delete-foo`.
+fn generic_names(name: &str) -> impl Iterator<Item = Cow<'_, str>> {
+    let mut base = name;
+    while let Some(stripped) = [SYMBOLIC_SUFFIX, "-rtl", "-ltr"]
+        .iter()
+        .find_map(|suffix| base.strip_suffix(suffix))
+        .filter(|stripped| !stripped.is_empty())
+    {
+        base = stripped;
+    }
+    let suffix = &name[base.len()..];
+
+    std::iter::successors(Some(base), |name| {
+        name.rsplit_once('-').map(|(head, _)| head)
+    })
+    .filter(|name| !name.is_empty())
+    .map(move |base| match suffix {
+        "" => Cow::Borrowed(base),
+        _ => Cow::Owned([base, suffix].concat()),
+    })
 }
 
 // WARNING: these test are highly dependent on your installed icon-themes.
 // If you want to run them, make sure you have 'Papirus' and 'Arc' icon-themes installed.
 #[cfg(test)]
 mod test {
-    use crate::{CACHE, CacheEntry, lookup};
+    use crate::temp_dir::{TempDir, write_theme};
+    use crate::{
+        CacheEntry, Context, DirectoryType, IconFormat, IconInfo, IconSource, IconStyle,
+        IconThemeRegistry, LookupError, StyledIcon, TextDirection, ThemeDirectory, list_themes,
+        lookup, lookup_any, theme_chain, themes,
+    };
     use speculoos::prelude::*;
     use std::path::PathBuf;
 
+    // A registry with a few icons of Adwaita in a `Fixture` theme inheriting from `hicolor`,
+    // and a `pixmaps` base path.
+    fn fixture(name: &str) -> (TempDir, IconThemeRegistry) {
+        let root = TempDir::new(name);
+        let theme = root.join("Fixture");
+        let directories = [
+            "24x24/legacy",
+            "24x24/places",
+            "48x48/places",
+            "scalable/actions",
+        ];
+        let icons = [
+            "24x24/legacy/edit-delete.png",
+            "24x24/legacy/go-next.png",
+            "24x24/legacy/go-next-rtl.png",
+            "24x24/places/folder-remote.png",
+            "48x48/places/folder-remote.png",
+            "scalable/actions/edit-delete-symbolic.svg",
+            "scalable/actions/go-next-symbolic.svg",
+            "scalable/actions/go-next-symbolic-rtl.svg",
+        ];
+        for directory in directories {
+            std::fs::create_dir_all(theme.join(directory)).unwrap();
+        }
+        for icon in icons {
+            std::fs::write(theme.join(icon), b"").unwrap();
+        }
+        std::fs::write(
+            theme.join("index.theme"),
+            format!(
+                "[Icon Theme]\nName=Fixture\nInherits=hicolor\nDirectories={}\n\n\
+                 [24x24/legacy]\nSize=24\nType=Fixed\nContext=Legacy\n\n\
+                 [24x24/places]\nSize=24\nType=Fixed\nContext=Places\n\n\
+                 [48x48/places]\nSize=48\nType=Fixed\nContext=Places\n\n\
+                 [scalable/actions]\nSize=16\nMinSize=8\nMaxSize=512\nType=Scalable\n\
+                 Context=Actions\n",
+                directories.join(",")
+            ),
+        )
+        .unwrap();
+
+        write_theme(&root.join("hicolor"), "Hicolor");
+        std::fs::write(root.join("hicolor/16x16/apps/gvim.png"), b"").unwrap();
+        std::fs::create_dir_all(root.join("pixmaps")).unwrap();
+        std::fs::write(root.join("pixmaps/distributor-logo.png"), b"").unwrap();
+
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([root.to_path_buf(), root.join("pixmaps")])
+            .build();
+        (root, registry)
+    }
+
     #[test]
     fn hicolor_firefox_24_png() {
         let firefox = lookup("firefox").find();
@@ -626,13 +1264,452 @@ mod test {
             .is_equal_to(PathBuf::from("/usr/share/pixmaps/archlinux-logo.png"));
     }
 
+    #[test]
+    fn should_strip_suffixes_for_generic_names() {
+        let names = super::generic_names("network-wireless-signal-good").collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            [
+                "network-wireless-signal-good",
+                "network-wireless-signal",
+                "network-wireless",
+                "network"
+            ]
+        );
+
+        let names = super::generic_names("edit-delete-foo-symbolic").collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "edit-delete-foo-symbolic",
+                "edit-delete-symbolic",
+                "edit-symbolic"
+            ]
+        );
+
+        let names = super::generic_names("go-next-foo-rtl-symbolic").collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "go-next-foo-rtl-symbolic",
+                "go-next-rtl-symbolic",
+                "go-rtl-symbolic"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_fallback_to_generic_name() {
+        let (root, registry) = fixture("generic");
+        let icon = lookup("folder-remote-ftp")
+            .with_registry(&registry)
+            .with_theme("Fixture")
+            .find();
+
+        asserting!("'folder-remote-ftp' should fallback to 'folder-remote'")
+            .that(&icon)
+            .is_some()
+            .is_equal_to(root.join("Fixture/24x24/places/folder-remote.png"));
+
+        let icon = lookup("folder-remote-ftp")
+            .with_registry(&registry)
+            .with_theme("Fixture")
+            .without_generic_fallback()
+            .find();
+
+        assert_that!(icon).is_none();
+    }
+
+    #[test]
+    fn should_order_symbolic_candidates() {
+        let names = lookup("edit-delete")
+            .with_style(IconStyle::Symbolic)
+            .candidate_names();
+
+        assert_eq!(
+            names,
+            [
+                ["edit-delete-symbolic"],
+                ["edit-symbolic"],
+                ["edit-delete"],
+                ["edit"]
+            ]
+        );
+
+        let names = lookup("edit-delete-symbolic")
+            .with_style(IconStyle::FullColor)
+            .without_generic_fallback()
+            .candidate_names();
+
+        assert_eq!(names, [["edit-delete"], ["edit-delete-symbolic"]]);
+    }
+
+    #[test]
+    fn should_search_all_names_on_each_generic_level() {
+        let names =
+            lookup_any(&["org.gnome.Nautilus", "", "system-file-manager"]).candidate_names();
+
+        assert_eq!(
+            names,
+            [
+                vec!["org.gnome.Nautilus", "system-file-manager"],
+                vec!["system-file"],
+                vec!["system"]
+            ]
+        );
+    }
+
+    #[test]
+    fn should_search_all_names_in_a_theme_first() {
+        let (root, registry) = fixture("any");
+        // `gvim` is only found in hicolor, the parent of the theme
+        let icon = lookup_any(&["gvim", "folder-remote"])
+            .with_registry(&registry)
+            .with_theme("Fixture")
+            .without_generic_fallback()
+            .find();
+
+        assert_eq!(
+            icon,
+            Some(root.join("Fixture/24x24/places/folder-remote.png"))
+        );
+
+        let icon = lookup_any(&["gvim"]).with_registry(&registry).find();
+        assert_eq!(icon, Some(root.join("hicolor/16x16/apps/gvim.png")));
+
+        assert_eq!(lookup_any(&[]).with_registry(&registry).find(), None);
+    }
+
+    #[test]
+    fn should_prefer_symbolic_variant() {
+        let (root, registry) = fixture("symbolic");
+        let find = |name: &str, style: IconStyle| {
+            lookup(name)
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_style(style)
+                .find_styled()
+        };
+        let symbolic = Some(StyledIcon {
+            path: root.join("Fixture/scalable/actions/edit-delete-symbolic.svg"),
+            symbolic: true,
+        });
+
+        assert_eq!(find("edit-delete", IconStyle::Symbolic), symbolic);
+
+        // Generic fallbacks of a symbolic icon name are symbolic.
+        assert_eq!(
+            find("edit-delete-foo-symbolic", IconStyle::default()),
+            symbolic
+        );
+
+        assert_eq!(
+            find("edit-delete-symbolic", IconStyle::FullColor),
+            Some(StyledIcon {
+                path: root.join("Fixture/24x24/legacy/edit-delete.png"),
+                symbolic: false,
+            })
+        );
+    }
+
+    #[test]
+    fn should_search_directional_variants_first() {
+        let builder = lookup("go-next").with_direction(TextDirection::Rtl);
+        assert_eq!(
+            builder.directional_names("go-next"),
+            ["go-next-rtl", "go-next"]
+        );
+        assert_eq!(
+            builder.directional_names("go-next-symbolic"),
+            [
+                "go-next-symbolic-rtl",
+                "go-next-rtl-symbolic",
+                "go-next-symbolic"
+            ]
+        );
+
+        let (root, registry) = fixture("direction");
+        let icon = lookup("go-next")
+            .with_registry(&registry)
+            .with_theme("Fixture")
+            .with_direction(TextDirection::Rtl)
+            .find();
+
+        assert_eq!(
+            icon,
+            Some(root.join("Fixture/24x24/legacy/go-next-rtl.png"))
+        );
+
+        let icon = lookup("go-next")
+            .with_registry(&registry)
+            .with_theme("Fixture")
+            .with_style(IconStyle::Symbolic)
+            .with_direction(TextDirection::Rtl)
+            .find();
+
+        assert_eq!(
+            icon,
+            Some(root.join("Fixture/scalable/actions/go-next-symbolic-rtl.svg"))
+        );
+    }
+
+    #[test]
+    fn should_describe_theme_icon() {
+        let (root, registry) = fixture("theme-icon");
+        let icon = lookup("folder-remote")
+            .with_registry(&registry)
+            .with_theme("Fixture")
+            .find_info();
+
+        assert_eq!(
+            icon,
+            Some(IconInfo {
+                path: root.join("Fixture/24x24/places/folder-remote.png"),
+                source: IconSource::Theme(ThemeDirectory {
+                    theme: "Fixture".into(),
+                    name: "24x24/places".into(),
+                    size: 24,
+                    scale: 1,
+                    type_: DirectoryType::Fixed,
+                    context: Some(Context::Places),
+                }),
+                format: IconFormat::Png,
+                symbolic: false,
+            })
+        );
+    }
+
+    #[test]
+    fn should_describe_pixmaps_icon() {
+        let (root, registry) = fixture("pixmaps-icon");
+        let icon = lookup("distributor-logo")
+            .with_registry(&registry)
+            .find_info();
+
+        assert_eq!(
+            icon,
+            Some(IconInfo {
+                path: root.join("pixmaps/distributor-logo.png"),
+                source: IconSource::Pixmaps,
+                format: IconFormat::Png,
+                symbolic: false,
+            })
+        );
+    }
+
+    #[test]
+    fn should_rank_all_candidates_like_find() {
+        let (root, registry) = fixture("candidates");
+        let lookup = || {
+            lookup("folder-remote")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+        };
+        let found = lookup().find_info();
+        let candidates = lookup().find_all().collect::<Vec<_>>();
+
+        assert_eq!(candidates.first().map(|c| &c.icon), found.as_ref());
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|c| (c.icon.path.clone(), c.distance))
+                .collect::<Vec<_>>(),
+            [
+                (root.join("Fixture/24x24/places/folder-remote.png"), Some(0)),
+                (
+                    root.join("Fixture/48x48/places/folder-remote.png"),
+                    Some(24)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_prefer_requested_context() {
+        let root = TempDir::new("context");
+        let theme = root.join("Fixture");
+        let directories = ["16x16/devices", "16x16/places", "16x16/legacy"];
+        for directory in directories {
+            std::fs::create_dir_all(theme.join(directory)).unwrap();
+            std::fs::write(theme.join(directory).join("computer.png"), b"").unwrap();
+        }
+        std::fs::write(
+            theme.join("index.theme"),
+            format!(
+                "[Icon Theme]\nName=Fixture\nDirectories={}\n\n\
+                 [16x16/devices]\nSize=16\nContext=Devices\n\n\
+                 [16x16/places]\nSize=16\nContext=Places\n\n\
+                 [16x16/legacy]\nSize=16\nContext=Legacy\n",
+                directories.join(",")
+            ),
+        )
+        .unwrap();
+
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([&*root])
+            .build();
+        let find = |context: Option<Context>| {
+            let lookup = lookup("computer")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16);
+            match context {
+                Some(context) => lookup.with_context(context),
+                None => lookup,
+            }
+            .find()
+        };
+
+        assert_eq!(find(None), Some(theme.join("16x16/devices/computer.png")));
+        assert_eq!(
+            find(Some(Context::Places)),
+            Some(theme.join("16x16/places/computer.png"))
+        );
+        assert_eq!(
+            find(Some(Context::Devices)),
+            Some(theme.join("16x16/devices/computer.png"))
+        );
+        assert_eq!(
+            find(Some(Context::Other("Legacy".into()))),
+            Some(theme.join("16x16/legacy/computer.png"))
+        );
+
+        // Any context matches when no directory has the requested one.
+        assert_eq!(
+            find(Some(Context::Actions)),
+            Some(theme.join("16x16/devices/computer.png"))
+        );
+    }
+
+    #[test]
+    fn should_report_lookup_errors() {
+        let root = TempDir::new("errors");
+        write_theme(&root.join("Fixture"), "Fixture");
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([&*root])
+            .build();
+
+        assert!(matches!(
+            lookup("").with_registry(&registry).try_find(),
+            Err(LookupError::EmptyName { theme }) if theme == "hicolor"
+        ));
+
+        assert!(matches!(
+            lookup("not-found").with_registry(&registry).with_theme("not-installed").try_find(),
+            Err(LookupError::ThemeNotFound { theme, base_paths })
+                if theme == "not-installed" && base_paths == [root.to_path_buf()]
+        ));
+
+        assert!(matches!(
+            lookup("not-found").with_registry(&registry).with_theme("Fixture").try_find(),
+            Err(LookupError::NotFound { name, theme, paths })
+                if name == "not-found" && theme == "Fixture" && paths == [root.join("Fixture")]
+        ));
+
+        assert_eq!(
+            lookup("folder-remote")
+                .with_theme("not-installed")
+                .try_find()
+                .ok(),
+            lookup("folder-remote").find(),
+        );
+    }
+
+    #[test]
+    fn should_get_theme_info() {
+        let adwaita = themes()
+            .into_iter()
+            .find(|theme| theme.id == "Adwaita")
+            .unwrap();
+
+        assert_eq!(adwaita.name, "Adwaita");
+        assert_eq!(adwaita.comment.as_deref(), Some("The Only One"));
+        assert_eq!(adwaita.inherits, ["hicolor"]);
+        assert_eq!(adwaita.example.as_deref(), Some("folder"));
+        assert!(!adwaita.hidden);
+        assert!(adwaita.directories.contains(&"scalable/apps".to_owned()));
+        assert!(
+            adwaita
+                .paths
+                .contains(&PathBuf::from("/usr/share/icons/Adwaita"))
+        );
+        assert!(list_themes().contains(&"Adwaita".to_owned()));
+    }
+
+    #[test]
+    fn should_list_theme_chain() {
+        let chain = theme_chain("Adwaita");
+
+        assert_eq!(chain.first().map(String::as_str), Some("Adwaita"));
+        assert!(chain.contains(&"hicolor".to_owned()));
+        assert_eq!(
+            theme_chain("not-installed").first().map(String::as_str),
+            Some("hicolor")
+        );
+    }
+
+    #[test]
+    fn should_cache_lookups_by_their_options() {
+        let root = TempDir::new("cache-options");
+        let theme = root.join("icons/Fixture");
+        for directory in ["16x16/places", "scalable/places"] {
+            std::fs::create_dir_all(theme.join(directory)).unwrap();
+        }
+        std::fs::write(
+            theme.join("index.theme"),
+            "[Icon Theme]\nName=Fixture\nDirectories=16x16/places,scalable/places\n\n\
+             [16x16/places]\nSize=16\n\n[scalable/places]\nSize=16\nType=Scalable\n",
+        )
+        .unwrap();
+        std::fs::write(theme.join("16x16/places/folder-remote.png"), b"").unwrap();
+        std::fs::write(theme.join("scalable/places/folder-remote.svg"), b"").unwrap();
+        std::fs::create_dir_all(root.join("extra")).unwrap();
+        std::fs::write(root.join("extra/folder-remote-ftp.png"), b"").unwrap();
+
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([root.join("icons")])
+            .build();
+        let extra_paths = [root.join("extra")];
+        let lookup = |cache: bool| {
+            let builder = lookup("folder-remote-ftp")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16);
+            if cache { builder.with_cache() } else { builder }
+        };
+
+        // Cached lookups with other options do not share their results.
+        for cache in [false, true] {
+            assert_eq!(
+                lookup(cache).find(),
+                Some(theme.join("16x16/places/folder-remote.png"))
+            );
+            assert_eq!(lookup(cache).without_generic_fallback().find(), None);
+            assert_eq!(
+                lookup(cache).force_svg().find(),
+                Some(theme.join("scalable/places/folder-remote.svg"))
+            );
+            assert_eq!(
+                lookup(cache).with_extra_paths(&extra_paths).find(),
+                Some(root.join("extra/folder-remote-ftp.png"))
+            );
+        }
+    }
+
     #[test]
     fn should_not_attempt_to_lookup_a_not_found_cached_icon() {
         let not_found = lookup("not-found").with_cache().find();
 
         assert_that!(not_found).is_none();
 
-        let expected_cache_result = CACHE.get("hicolor", 24, 1, "not-found");
+        let expected_cache_result = IconThemeRegistry::global().cache.get(
+            "hicolor",
+            24,
+            1,
+            lookup("not-found").variant(),
+            "not-found",
+        );
 
         assert!(
             matches!(expected_cache_result, CacheEntry::NotFound(..)),
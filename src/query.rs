@@ -0,0 +1,134 @@
+use crate::{Context, IconStyle, LookupBuilder, Names, TextDirection};
+use std::path::PathBuf;
+
+/// An owned icon lookup query, holding the same parameters as a [`LookupBuilder`].
+///
+/// Unlike the builder, a query can be stored in a widget state, sent to another thread,
+/// or used as a map key. A query does not hold the registry of the builder, lookups built
+/// from a query use the global registry unless given another one.
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::{lookup, IconQuery};
+///
+/// let query = IconQuery::from(&lookup("firefox").with_size(48).with_theme("Papirus"));
+///
+/// let icon = std::thread::spawn(move || query.lookup().find()).join();
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct IconQuery {
+    /// The icon names, in order of preference.
+    pub names: Vec<String>,
+    pub theme: String,
+    pub size: u16,
+    pub scale: u16,
+    pub cache: bool,
+    pub force_svg: bool,
+    pub extra_paths: Vec<PathBuf>,
+    pub generic_fallback: bool,
+    pub style: IconStyle,
+    pub direction: TextDirection,
+    pub context: Option<Context>,
+}
+
+impl IconQuery {
+    /// Create a query for the given icon name, with the default lookup parameters.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            names: vec![name.into()],
+            ..Self::default()
+        }
+    }
+
+    /// Build a lookup for this query.
+    pub fn lookup(&self) -> LookupBuilder<'_> {
+        LookupBuilder::from(self)
+    }
+}
+
+impl Default for IconQuery {
+    fn default() -> Self {
+        let mut query = Self::from(&LookupBuilder::new(""));
+        query.names.clear();
+        query
+    }
+}
+
+impl From<&LookupBuilder<'_>> for IconQuery {
+    fn from(builder: &LookupBuilder<'_>) -> Self {
+        Self {
+            names: builder.names().map(str::to_owned).collect(),
+            theme: builder.theme.to_owned(),
+            size: builder.size,
+            scale: builder.scale,
+            cache: builder.cache,
+            force_svg: builder.force_svg,
+            extra_paths: builder.extra_paths.to_vec(),
+            generic_fallback: builder.generic_fallback,
+            style: builder.style,
+            direction: builder.direction,
+            context: builder.context.clone(),
+        }
+    }
+}
+
+impl<'a> From<&'a IconQuery> for LookupBuilder<'a> {
+    fn from(query: &'a IconQuery) -> Self {
+        let (name, other_names) = match query.names.split_first() {
+            Some((name, other_names)) => (name.as_str(), other_names),
+            None => ("", &[][..]),
+        };
+
+        LookupBuilder {
+            name,
+            other_names: Names::Owned(other_names),
+            cache: query.cache,
+            force_svg: query.force_svg,
+            scale: query.scale,
+            size: query.size,
+            theme: &query.theme,
+            extra_paths: &query.extra_paths,
+            generic_fallback: query.generic_fallback,
+            style: query.style,
+            direction: query.direction,
+            context: query.context.clone(),
+            registry: None,
+            icon_cache: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Context, IconQuery, IconStyle, lookup, lookup_any};
+
+    #[test]
+    fn should_convert_builder_to_query_and_back() {
+        let query = IconQuery::from(
+            &lookup_any(&["org.gnome.Nautilus", "system-file-manager"])
+                .with_size(48)
+                .with_theme("Adwaita")
+                .with_style(IconStyle::Symbolic)
+                .with_context(Context::Applications),
+        );
+
+        assert_eq!(query.names, ["org.gnome.Nautilus", "system-file-manager"]);
+        assert_eq!(query.theme, "Adwaita");
+        assert_eq!(query.size, 48);
+        assert_eq!(IconQuery::from(&query.lookup()), query);
+        assert_eq!(query.lookup().find(), query.clone().lookup().find());
+    }
+
+    #[test]
+    fn should_default_to_builder_parameters() {
+        assert_eq!(
+            IconQuery::new("firefox"),
+            IconQuery::from(&lookup("firefox"))
+        );
+        assert_eq!(IconQuery::default().lookup().find(), None);
+    }
+}
@@ -0,0 +1,855 @@
+use crate::IconCache;
+use crate::ThemeInfo;
+use crate::disk_cache::DiskCache;
+use crate::fingerprint;
+use crate::theme::{self, Theme};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+static GLOBAL: LazyLock<IconThemeRegistry> = LazyLock::new(IconThemeRegistry::new);
+
+// How often cached state is checked against the themes by default.
+pub(crate) const REVALIDATION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The icon themes installed in the icon base directories.
+///
+/// Themes are discovered once when the registry is created. Long-running applications
+/// can call [`reload_if_changed`] to pick up themes installed or removed afterwards.
+/// Lookups use the [`global`] registry unless given another one with
+/// [`LookupBuilder::with_registry`](crate::LookupBuilder::with_registry).
+///
+/// [`reload_if_changed`]: IconThemeRegistry::reload_if_changed
+/// [`global`]: IconThemeRegistry::global
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::IconThemeRegistry;
+///
+/// // After an application or a theme was installed.
+/// if IconThemeRegistry::global().reload_if_changed() {
+///     println!("icon themes changed");
+/// }
+/// # }
+pub struct IconThemeRegistry {
+    pub(crate) builder: RegistryBuilder,
+    themes: RwLock<Arc<Themes>>,
+    pub(crate) cache: IconCache,
+}
+
+/// Build an [`IconThemeRegistry`] searching the given base directories.
+///
+/// By default, the base directories are derived from the XDG environment variables and
+/// from the home directory. With explicit base paths or a sysroot, the user directories
+/// are never searched, which allows hermetic lookups in tests and in OS images.
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::{lookup, IconThemeRegistry};
+///
+/// // The system icons of an image mounted in /mnt/image.
+/// let registry = IconThemeRegistry::builder().with_sysroot("/mnt/image").build();
+///
+/// let icon = lookup("firefox").with_registry(&registry).find();
+/// # }
+#[derive(Debug, Clone)]
+pub struct RegistryBuilder {
+    base_paths: Option<Vec<PathBuf>>,
+    sysroot: Option<PathBuf>,
+    app_exports: bool,
+    legacy_order: bool,
+    file_index: bool,
+    persistent_cache: Option<PathBuf>,
+    pub(crate) revalidation_interval: Duration,
+    pub(crate) fallback_themes: Vec<String>,
+}
+
+// The fingerprints of the theme chain of a theme, by extra paths of the lookups, and when they
+// were computed.
+type Fingerprints = BTreeMap<Box<[PathBuf]>, (u64, Instant)>;
+
+// The themes discovered at a given time. Lookups keep their own reference to it,
+// so that a reload does not interfere with running lookups.
+pub(crate) struct Themes {
+    base_dirs: Vec<BaseDir>,
+    // The existing base directories, in order of precedence.
+    pub(crate) base_paths: Vec<PathBuf>,
+    pub(crate) themes: BTreeMap<Vec<u8>, Vec<Theme>>,
+    pub(crate) disk_cache: Option<DiskCache>,
+    // The fingerprints of the theme chains, by theme.
+    fingerprints: Mutex<BTreeMap<String, Fingerprints>>,
+}
+
+// A base directory candidate and its state when the themes were discovered.
+#[derive(Debug, PartialEq)]
+struct BaseDir {
+    path: PathBuf,
+    exists: bool,
+    modified: Option<SystemTime>,
+}
+
+impl IconThemeRegistry {
+    /// Discover the themes installed in the base directories of the environment.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// Configure the base directories of a new registry.
+    pub fn builder() -> RegistryBuilder {
+        RegistryBuilder::default()
+    }
+
+    /// The registry used by lookups by default, and by [`themes`](crate::themes),
+    /// [`list_themes`](crate::list_themes) and [`theme_chain`](crate::theme_chain).
+    pub fn global() -> &'static Self {
+        &GLOBAL
+    }
+
+    /// Discover the installed themes again, and clear the lookup cache of this registry.
+    pub fn reload(&self) {
+        self.replace(Themes::discover(self.builder.base_dirs(), &self.builder));
+    }
+
+    /// Discover the installed themes again if a base directory was created, removed or
+    /// modified since the last discovery. Returns whether the themes were reloaded.
+    pub fn reload_if_changed(&self) -> bool {
+        let base_dirs = self.builder.base_dirs();
+        if base_dirs == self.snapshot().base_dirs {
+            return false;
+        }
+
+        self.replace(Themes::discover(base_dirs, &self.builder));
+        true
+    }
+
+    /// The existing base directories searched for themes, in order of precedence.
+    pub fn base_paths(&self) -> Vec<PathBuf> {
+        self.snapshot().base_paths.clone()
+    }
+
+    /// Return the metadata of the themes of this registry, sorted by theme directory name.
+    pub fn themes(&self) -> Vec<ThemeInfo> {
+        self.snapshot()
+            .themes
+            .iter()
+            .filter_map(|(id, themes)| ThemeInfo::parse(&String::from_utf8_lossy(id), themes))
+            .collect()
+    }
+
+    // The names of the themes of this registry which have a `Name`, sorted by theme
+    // directory name.
+    pub(crate) fn theme_names(&self) -> Vec<String> {
+        self.snapshot()
+            .themes
+            .iter()
+            .filter_map(|(id, themes)| ThemeInfo::parse_named(&String::from_utf8_lossy(id), themes))
+            .map(|theme| theme.name)
+            .collect()
+    }
+
+    /// Return the names of the themes searched by a lookup in the given theme, in order.
+    pub fn theme_chain(&self, theme: &str) -> Vec<String> {
+        self.snapshot()
+            .chain_names(theme, &self.builder.fallback_themes)
+            .into_iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect()
+    }
+
+    /// Write the lookups cached since the last write to the persistent cache, if enabled
+    /// with [`RegistryBuilder::with_persistent_cache`].
+    pub fn flush_persistent_cache(&self) -> io::Result<()> {
+        match &self.snapshot().disk_cache {
+            Some(disk_cache) => disk_cache.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// The cache of the lookups made with this registry, unless they were given another
+    /// cache with [`LookupBuilder::with_icon_cache`](crate::LookupBuilder::with_icon_cache).
+    pub fn cache(&self) -> &IconCache {
+        &self.cache
+    }
+
+    #[inline]
+    pub(crate) fn snapshot(&self) -> Arc<Themes> {
+        self.themes.read().unwrap().clone()
+    }
+
+    // Forget what is known of the directory of an icon file which was added or removed. The
+    // fingerprints are computed again by the next lookups, so that the results cached in
+    // memory and in the persistent cache are checked against the new state of the themes.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn forget_icon_file(&self, path: &Path) {
+        let themes = self.snapshot();
+        themes.fingerprints.lock().unwrap().clear();
+
+        let Some(directory) = path.parent() else {
+            return;
+        };
+
+        for theme in themes.themes.values().flatten() {
+            if let Some(directory) = directory
+                .strip_prefix(&theme.path.0)
+                .ok()
+                .and_then(Path::to_str)
+            {
+                theme.forget_directory(directory);
+            }
+        }
+    }
+
+    // Open the `icon-theme.cache` of the theme directory again when it is next used.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn forget_gtk_cache(&self, theme_dir: &Path) {
+        for theme in self.snapshot().themes.values().flatten() {
+            if theme.path.0 == theme_dir {
+                theme.forget_gtk_cache();
+            }
+        }
+    }
+
+    fn replace(&self, themes: Themes) {
+        *self.themes.write().unwrap() = Arc::new(themes);
+        self.cache.clear();
+    }
+}
+
+impl Default for RegistryBuilder {
+    fn default() -> Self {
+        Self {
+            base_paths: None,
+            sysroot: None,
+            app_exports: true,
+            legacy_order: false,
+            file_index: false,
+            persistent_cache: None,
+            revalidation_interval: REVALIDATION_INTERVAL,
+            fallback_themes: ["Cosmic", "hicolor", "gnome", "Yaru"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl Default for IconThemeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegistryBuilder {
+    /// Search the given base directories, in order of precedence, instead of the
+    /// directories of the environment.
+    pub fn with_base_paths<P: Into<PathBuf>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.base_paths = Some(paths.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Search the base directories relative to the given root directory, such as the root
+    /// of a chroot or of an OS image. Without explicit base paths, the system directories
+    /// `/usr/local/share` and `/usr/share` of the root directory are searched.
+    pub fn with_sysroot(mut self, sysroot: impl Into<PathBuf>) -> Self {
+        self.sysroot = Some(sysroot.into());
+        self
+    }
+
+    /// Do not search the icon directories exported by Flatpak and Snap applications,
+    /// unless they are part of `$XDG_DATA_DIRS` or of the explicit base paths.
+    ///
+    /// By default, `$XDG_DATA_HOME/flatpak/exports/share/icons`,
+    /// `/var/lib/flatpak/exports/share/icons` and `/var/lib/snapd/desktop/icons` are
+    /// searched when they exist. With a sysroot, only the system directories are searched.
+    pub fn without_app_exports(mut self) -> Self {
+        self.app_exports = false;
+        self
+    }
+
+    /// Search the base directories in the order of the previous versions of this crate.
+    ///
+    /// By default, the base directories are searched in the order of the specification:
+    /// `$HOME/.icons`, `$XDG_DATA_HOME/icons`, `$XDG_DATA_DIRS/icons` and then the pixmaps
+    /// directories, so that the themes of the user take precedence over the system ones.
+    /// The legacy order searches `$XDG_DATA_DIRS` first and `$HOME/.icons` last.
+    pub fn with_legacy_base_order(mut self) -> Self {
+        self.legacy_order = true;
+        self
+    }
+
+    /// Index the files of the theme directories in memory, instead of probing each directory
+    /// for each icon file extension.
+    ///
+    /// Each directory is read once when first searched, and read again when its modification
+    /// time changed, which is checked at most once per
+    /// [revalidation interval](Self::with_revalidation_interval). Themes with a valid
+    /// `icon-theme.cache` use it instead. This makes lookups of missing icons much cheaper,
+    /// at the cost of the memory holding the names of the files of the searched directories.
+    pub fn with_file_index(mut self) -> Self {
+        self.file_index = true;
+        self
+    }
+
+    /// Persist the results of cached lookups in `$XDG_CACHE_HOME/cosmic-freedesktop-icons`,
+    /// sharing them with other processes and across sessions.
+    ///
+    /// Lookups made [`with_cache`](crate::LookupBuilder::with_cache) and without extra paths
+    /// use this cache when their result is not in memory. Each theme chain has its own cache
+    /// file, which is ignored as soon as a directory of one of its themes or a base directory
+    /// changed, as checked at most once per
+    /// [revalidation interval](Self::with_revalidation_interval). New results are written at
+    /// most every 2 seconds, when the registry is reloaded or dropped, and by
+    /// [`IconThemeRegistry::flush_persistent_cache`].
+    ///
+    /// ## Example
+    /// ```rust,no_run
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::{lookup, IconThemeRegistry};
+    ///
+    /// let registry = IconThemeRegistry::builder().with_persistent_cache().build();
+    ///
+    /// let icon = lookup("firefox").with_registry(&registry).with_cache().find();
+    /// # }
+    pub fn with_persistent_cache(self) -> Self {
+        match xdg::BaseDirectories::new().get_cache_home() {
+            Some(cache_home) => {
+                self.with_persistent_cache_dir(cache_home.join("cosmic-freedesktop-icons"))
+            }
+            None => {
+                tracing::warn!("no cache directory found, lookups are not persisted");
+                self
+            }
+        }
+    }
+
+    /// Persist the results of cached lookups in the given directory instead of
+    /// `$XDG_CACHE_HOME`, see [`with_persistent_cache`](Self::with_persistent_cache).
+    pub fn with_persistent_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.persistent_cache = Some(dir.into());
+        self
+    }
+
+    /// Check whether cached lookups are still valid at most once per the given interval,
+    /// 5 seconds by default.
+    ///
+    /// As recommended by the specification, cached lookups record the modification times of
+    /// the directories of the theme chain and of the base directories, and are looked up again
+    /// once one of them changed. Cached icons whose file was removed are never returned.
+    /// The `icon-theme.cache` files of the themes, which are ignored while they are older than
+    /// a directory of their theme, and the directories of the [file index](Self::with_file_index)
+    /// are checked at the same interval.
+    pub fn with_revalidation_interval(mut self, interval: Duration) -> Self {
+        self.revalidation_interval = interval;
+        self
+    }
+
+    /// Search the given themes, in order, after the requested theme and its parents.
+    ///
+    /// The default fallback themes are `Cosmic`, `hicolor`, `gnome` and `Yaru`: GNOME
+    /// applications may rely on the gnome theme, and Ubuntu applications may require Yaru.
+    /// The `hicolor` theme is searched last if it is not part of the fallback themes,
+    /// as required by the specification.
+    ///
+    /// ## Example
+    /// ```rust
+    /// # fn main() {
+    /// use cosmic_freedesktop_icons::IconThemeRegistry;
+    ///
+    /// let registry = IconThemeRegistry::builder()
+    ///     .with_fallback_themes(["breeze", "hicolor"])
+    ///     .build();
+    /// # }
+    pub fn with_fallback_themes<S: Into<String>>(
+        mut self,
+        themes: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.fallback_themes = themes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Search the given themes after the current fallback themes.
+    pub fn with_additional_fallback_themes<S: Into<String>>(
+        mut self,
+        themes: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.fallback_themes
+            .extend(themes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only search the requested theme, its parents and the `hicolor` theme.
+    pub fn without_fallback_themes(mut self) -> Self {
+        self.fallback_themes.clear();
+        self
+    }
+
+    /// Discover the themes installed in the configured base directories.
+    pub fn build(self) -> IconThemeRegistry {
+        IconThemeRegistry {
+            themes: RwLock::new(Arc::new(Themes::discover(self.base_dirs(), &self))),
+            cache: IconCache::default(),
+            builder: self,
+        }
+    }
+
+    fn base_dirs(&self) -> Vec<BaseDir> {
+        let candidates = match (&self.base_paths, &self.sysroot) {
+            (Some(paths), _) => paths.clone(),
+            (None, Some(_)) => {
+                theme::system_base_path_candidates(self.app_exports, self.legacy_order)
+            }
+            (None, None) => theme::base_path_candidates(self.app_exports, self.legacy_order),
+        };
+
+        // The same directory may be listed several times, through symbolic links for instance.
+        let mut canonical_paths = Vec::new();
+        candidates
+            .into_iter()
+            .map(|path| match &self.sysroot {
+                Some(sysroot) => sysroot.join(path.strip_prefix("/").unwrap_or(&path)),
+                None => path,
+            })
+            .filter(|path| {
+                let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+                let new = !canonical_paths.contains(&canonical);
+                canonical_paths.push(canonical);
+                new
+            })
+            .map(|path| {
+                let metadata = std::fs::metadata(&path).ok();
+                BaseDir {
+                    exists: metadata.is_some(),
+                    modified: metadata.and_then(|metadata| metadata.modified().ok()),
+                    path,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Themes {
+    fn discover(base_dirs: Vec<BaseDir>, builder: &RegistryBuilder) -> Self {
+        let base_paths = base_dirs
+            .iter()
+            .filter(|dir| dir.exists)
+            .map(|dir| dir.path.clone())
+            .collect::<Vec<_>>();
+
+        let mut themes = theme::get_all_themes(&base_paths);
+        for theme in themes.values_mut().flatten() {
+            theme.set_revalidation_interval(builder.revalidation_interval);
+            if builder.file_index {
+                theme.enable_file_index(builder.revalidation_interval);
+            }
+        }
+
+        Self {
+            themes,
+            disk_cache: builder.persistent_cache.clone().map(DiskCache::new),
+            fingerprints: Mutex::default(),
+            base_paths,
+            base_dirs,
+        }
+    }
+
+    // The names of the themes searched by a lookup in the given theme, in order.
+    pub(crate) fn chain_names(&self, theme: &str, fallbacks: &[String]) -> Vec<&[u8]> {
+        let themes = &self.themes;
+
+        // The active icon theme, or 'hicolor' if it is not installed, and its inherits.
+        let theme = if themes.contains_key(theme.as_bytes()) {
+            theme
+        } else {
+            "hicolor"
+        };
+        let mut chain = theme::inheritance_chain(themes, theme.as_bytes());
+
+        // The fallback themes of the registry, then the hicolor icon theme
+        // if it was not previously searched.
+        let hicolor =
+            (!fallbacks.iter().any(|fallback| fallback == "hicolor")).then_some("hicolor");
+        for fallback in fallbacks.iter().map(String::as_str).chain(hicolor) {
+            if let Some((name, _)) = themes.get_key_value(fallback.as_bytes())
+                && !chain.contains(&name.as_slice())
+            {
+                chain.push(name);
+            }
+        }
+
+        chain
+    }
+
+    // The fingerprint of the chain of the given theme and of the extra paths, computed again
+    // when it is older than the interval.
+    pub(crate) fn fingerprint<'t>(
+        &self,
+        theme: &str,
+        extra_paths: &[PathBuf],
+        interval: Duration,
+        chain: impl FnOnce() -> Vec<&'t Theme>,
+    ) -> u64 {
+        if let Some(&(fingerprint, checked)) = self
+            .fingerprints
+            .lock()
+            .unwrap()
+            .get(theme)
+            .and_then(|fingerprints| fingerprints.get(extra_paths))
+            && checked.elapsed() < interval
+        {
+            return fingerprint;
+        }
+
+        let fingerprint = fingerprint::fingerprint(&chain(), &self.base_paths, extra_paths);
+        self.fingerprints
+            .lock()
+            .unwrap()
+            .entry(theme.to_string())
+            .or_default()
+            .insert(extra_paths.into(), (fingerprint, Instant::now()));
+        fingerprint
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::temp_dir::{TempDir, write_theme};
+    use crate::{IconThemeRegistry, lookup};
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    // A temporary root directory with a `Fixture` theme holding an `app` icon.
+    struct Fixture(TempDir);
+
+    impl Fixture {
+        fn add_theme(&self, name: &str, inherits: &str) {
+            let theme = self.path("usr/share/icons").join(name);
+            std::fs::create_dir_all(&theme).unwrap();
+            std::fs::write(
+                theme.join("index.theme"),
+                format!("[Icon Theme]\nName={name}\nInherits={inherits}\n"),
+            )
+            .unwrap();
+        }
+
+        fn new(name: &str) -> Self {
+            let root = TempDir::new(&format!("registry-{name}"));
+            let theme = root.join("usr/share/icons/Fixture");
+            write_theme(&theme, "Fixture");
+            std::fs::create_dir_all(root.join("usr/share/pixmaps")).unwrap();
+            std::fs::write(theme.join("16x16/apps/app.png"), b"").unwrap();
+            Self(root)
+        }
+
+        fn path(&self, path: &str) -> PathBuf {
+            self.0.join(path)
+        }
+    }
+
+    fn assert_hermetic(registry: &IconThemeRegistry, root: &Path) {
+        assert!(!registry.base_paths().is_empty());
+        assert!(
+            registry
+                .base_paths()
+                .iter()
+                .all(|path| path.starts_with(root))
+        );
+        assert_eq!(registry.theme_chain("Fixture"), ["Fixture"]);
+        assert_eq!(registry.theme_chain("hicolor"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn should_search_sysroot() {
+        let fixture = Fixture::new("sysroot");
+        let registry = IconThemeRegistry::builder()
+            .with_sysroot(&*fixture.0)
+            .without_app_exports()
+            .build();
+
+        assert_hermetic(&registry, &fixture.0);
+        assert_eq!(
+            registry.base_paths(),
+            [
+                fixture.path("usr/share/icons"),
+                fixture.path("usr/share/pixmaps")
+            ]
+        );
+        assert_eq!(
+            lookup("app")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .find(),
+            Some(fixture.path("usr/share/icons/Fixture/16x16/apps/app.png"))
+        );
+    }
+
+    #[test]
+    fn should_search_explicit_base_paths() {
+        let fixture = Fixture::new("base-paths");
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([fixture.path("usr/share/icons")])
+            .build();
+
+        assert_hermetic(&registry, &fixture.0);
+        assert!(lookup("firefox").with_registry(&registry).find().is_none());
+
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths(["/usr/share/icons"])
+            .with_sysroot(&*fixture.0)
+            .build();
+
+        assert_eq!(registry.base_paths(), [fixture.path("usr/share/icons")]);
+    }
+
+    #[test]
+    fn should_index_theme_files() {
+        let fixture = Fixture::new("file-index");
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([fixture.path("usr/share/icons")])
+            .with_file_index()
+            .build();
+        let find = |name| {
+            lookup(name)
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .find()
+        };
+
+        assert_eq!(
+            find("app"),
+            Some(fixture.path("usr/share/icons/Fixture/16x16/apps/app.png"))
+        );
+        assert_eq!(find("missing"), None);
+
+        // Reloading the themes starts a new index.
+        std::fs::write(
+            fixture.path("usr/share/icons/Fixture/16x16/apps/missing.svg"),
+            b"",
+        )
+        .unwrap();
+        registry.reload();
+        assert_eq!(
+            find("missing"),
+            Some(fixture.path("usr/share/icons/Fixture/16x16/apps/missing.svg"))
+        );
+    }
+
+    #[test]
+    fn should_persist_cached_lookups() {
+        let fixture = Fixture::new("persistent-cache");
+        let apps = fixture.path("usr/share/icons/Fixture/16x16/apps");
+        let build = || {
+            IconThemeRegistry::builder()
+                .with_base_paths([fixture.path("usr/share/icons")])
+                .with_persistent_cache_dir(fixture.path("cache"))
+                .build()
+        };
+        let find = |registry: &IconThemeRegistry| {
+            lookup("missing")
+                .with_registry(registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .with_cache()
+                .find()
+        };
+
+        let registry = build();
+        assert_eq!(find(&registry), None);
+        registry.flush_persistent_cache().unwrap();
+
+        // Another registry finds the result in the persistent cache, without searching the theme.
+        let modified = std::fs::metadata(&apps).unwrap().modified().unwrap();
+        std::fs::write(apps.join("missing.png"), b"").unwrap();
+        std::fs::File::open(&apps)
+            .and_then(|dir| dir.set_modified(modified))
+            .unwrap();
+        assert_eq!(find(&build()), None);
+
+        // The cache is ignored once the theme changed.
+        std::fs::File::open(&apps)
+            .and_then(|dir| dir.set_modified(modified + Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(find(&build()), Some(apps.join("missing.png")));
+    }
+
+    #[test]
+    fn should_revalidate_cached_lookups() {
+        let fixture = Fixture::new("revalidation");
+        let apps = fixture.path("usr/share/icons/Fixture/16x16/apps");
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([fixture.path("usr/share/icons")])
+            .with_revalidation_interval(Duration::from_secs(60))
+            .build();
+        let find = |name| {
+            lookup(name)
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .with_cache()
+                .find()
+        };
+
+        assert_eq!(find("app"), Some(apps.join("app.png")));
+        assert_eq!(find("new"), None);
+
+        // A removed icon is never returned, even before the themes are checked again.
+        std::fs::remove_file(apps.join("app.png")).unwrap();
+        assert_eq!(find("app"), None);
+
+        // A new icon is found once the themes are checked again.
+        std::fs::write(apps.join("new.png"), b"").unwrap();
+        std::fs::File::open(&apps)
+            .and_then(|dir| dir.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(find("new"), None);
+        for (_, checked) in registry
+            .snapshot()
+            .fingerprints
+            .lock()
+            .unwrap()
+            .values_mut()
+            .flat_map(BTreeMap::values_mut)
+        {
+            *checked -= Duration::from_secs(60);
+        }
+        assert_eq!(find("new"), Some(apps.join("new.png")));
+    }
+
+    #[test]
+    fn should_revalidate_cached_lookups_in_extra_paths() {
+        let fixture = Fixture::new("revalidation-extra");
+        let extra_paths = [fixture.path("extra")];
+        let nested = fixture.path("extra/nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([fixture.path("usr/share/icons")])
+            .with_revalidation_interval(Duration::ZERO)
+            .build();
+        let find = || {
+            lookup("extra")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_extra_paths(&extra_paths)
+                .with_cache()
+                .find()
+        };
+
+        assert_eq!(find(), None);
+
+        std::fs::write(nested.join("extra.png"), b"").unwrap();
+        std::fs::File::open(&nested)
+            .and_then(|dir| dir.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(find(), Some(nested.join("extra.png")));
+    }
+
+    #[test]
+    fn should_only_list_named_themes() {
+        let fixture = Fixture::new("named-themes");
+        let default = fixture.path("usr/share/icons/default");
+        std::fs::create_dir_all(&default).unwrap();
+        std::fs::write(
+            default.join("index.theme"),
+            "[Icon Theme]\nInherits=Fixture\n",
+        )
+        .unwrap();
+        let registry = IconThemeRegistry::builder()
+            .with_base_paths([fixture.path("usr/share/icons")])
+            .build();
+
+        assert_eq!(registry.theme_names(), ["Fixture"]);
+        assert_eq!(
+            registry
+                .themes()
+                .into_iter()
+                .map(|theme| theme.name)
+                .collect::<Vec<_>>(),
+            ["Fixture", "default"]
+        );
+    }
+
+    #[test]
+    fn should_configure_fallback_themes() {
+        let fixture = Fixture::new("fallback-themes");
+        for theme in ["hicolor", "Cosmic", "breeze"] {
+            fixture.add_theme(theme, "");
+        }
+        fixture.add_theme("Child", "Fixture,hicolor");
+
+        let chain = |builder: crate::RegistryBuilder| {
+            builder
+                .with_sysroot(&*fixture.0)
+                .build()
+                .theme_chain("Child")
+        };
+
+        assert_eq!(
+            chain(IconThemeRegistry::builder()),
+            ["Child", "Fixture", "Cosmic", "hicolor"]
+        );
+        assert_eq!(
+            chain(IconThemeRegistry::builder().without_fallback_themes()),
+            ["Child", "Fixture", "hicolor"]
+        );
+        assert_eq!(
+            chain(IconThemeRegistry::builder().with_fallback_themes(["breeze"])),
+            ["Child", "Fixture", "breeze", "hicolor"]
+        );
+        assert_eq!(
+            chain(IconThemeRegistry::builder().with_additional_fallback_themes(["breeze"])),
+            ["Child", "Fixture", "Cosmic", "hicolor", "breeze"]
+        );
+    }
+
+    #[test]
+    fn should_search_app_exports_once() {
+        let fixture = Fixture::new("app-exports");
+        let flatpak = fixture.path("var/lib/flatpak/exports/share/icons");
+        std::fs::create_dir_all(&flatpak).unwrap();
+        std::os::unix::fs::symlink(&flatpak, fixture.path("usr/share/flatpak-icons")).unwrap();
+
+        let registry = IconThemeRegistry::builder()
+            .with_sysroot(&*fixture.0)
+            .build();
+        assert_eq!(
+            registry.base_paths(),
+            [
+                fixture.path("usr/share/icons"),
+                flatpak.clone(),
+                fixture.path("usr/share/pixmaps"),
+            ]
+        );
+
+        let registry = IconThemeRegistry::builder()
+            .with_sysroot(&*fixture.0)
+            .with_base_paths([
+                "/var/lib/flatpak/exports/share/icons",
+                "/usr/share/flatpak-icons",
+                "/usr/share/icons",
+            ])
+            .build();
+        assert_eq!(
+            registry.base_paths(),
+            [flatpak, fixture.path("usr/share/icons")]
+        );
+    }
+
+    #[test]
+    fn should_reload_only_if_changed() {
+        let registry = IconThemeRegistry::new();
+        let themes = registry.themes();
+
+        assert!(!registry.reload_if_changed());
+        registry.reload();
+        assert_eq!(registry.themes(), themes);
+        assert_eq!(
+            registry.base_paths(),
+            IconThemeRegistry::global().base_paths()
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Detection of the icon theme configured by the user's desktop environment.
+
+use std::path::{Path, PathBuf};
+use xdg::BaseDirectories;
+
+/// Return the icon theme configured in the desktop settings of the user.
+///
+/// The following configuration files are read in order, and the first theme found is returned:
+/// 1. GTK's `gtk-4.0/settings.ini` and `gtk-3.0/settings.ini` (`gtk-icon-theme-name`)
+/// 2. KDE's `kdeglobals` (`Theme` in the `[Icons]` group)
+/// 3. COSMIC's `cosmic/com.system76.CosmicTk/v1/icon_theme`
+/// 4. xsettingsd's `xsettingsd/xsettingsd.conf` (`Net/IconThemeName`)
+///
+/// All of them are searched in `$XDG_CONFIG_HOME` first, along with `~/.xsettingsd`, then in
+/// `$XDG_CONFIG_DIRS`, so that any setting of the user overrides the settings of the system.
+/// The dconf keyfiles of `/etc/dconf/db/*.d` (`icon-theme` in `org/gnome/desktop/interface`) are
+/// read last. If no theme is configured, `hicolor` is returned.
+///
+/// ## Example
+/// ```rust
+/// # fn main() {
+/// use cosmic_freedesktop_icons::{current_theme, lookup};
+///
+/// let theme = current_theme();
+/// let icon = lookup("firefox").with_theme(&theme).find();
+/// # }
+/// ```
+pub fn current_theme() -> String {
+    SettingsPaths::from_env()
+        .current_theme()
+        .unwrap_or_else(|| "hicolor".to_owned())
+}
+
+// The directories where desktop settings are read from.
+struct SettingsPaths {
+    config_home: Option<PathBuf>,
+    config_dirs: Vec<PathBuf>,
+    home: Option<PathBuf>,
+    dconf_db: PathBuf,
+}
+
+impl SettingsPaths {
+    fn from_env() -> Self {
+        let base_dirs = BaseDirectories::new();
+        Self {
+            config_home: base_dirs.get_config_home(),
+            config_dirs: base_dirs.get_config_dirs(),
+            home: std::env::home_dir(),
+            dconf_db: PathBuf::from("/etc/dconf/db"),
+        }
+    }
+
+    fn current_theme(&self) -> Option<String> {
+        let home_file = self.home.as_ref().map(|home| home.join(".xsettingsd"));
+
+        // The settings of the user override those of the system, whatever their source.
+        config_theme(self.config_home.as_slice(), home_file.as_deref())
+            .or_else(|| config_theme(&self.config_dirs, None))
+            .or_else(|| self.dconf_theme())
+    }
+
+    fn dconf_theme(&self) -> Option<String> {
+        let mut keyfiles = keyfile_dirs(&self.dconf_db)
+            .flat_map(|dir| read_dir_sorted(&dir))
+            .collect::<Vec<_>>();
+        // Later keyfiles override the earlier ones.
+        keyfiles.reverse();
+
+        keyfiles.iter().find_map(|path| {
+            let file = std::fs::read_to_string(path).ok()?;
+            keyfile_value(&file, "org/gnome/desktop/interface", "icon-theme")
+        })
+    }
+}
+
+// Get the theme configured in the given directories, by GTK, KDE, COSMIC or xsettingsd.
+fn config_theme(config_dirs: &[PathBuf], xsettings_file: Option<&Path>) -> Option<String> {
+    let config_files = |file: &'static str| {
+        config_dirs
+            .iter()
+            .filter_map(move |dir| std::fs::read_to_string(dir.join(file)).ok())
+    };
+
+    let gtk_theme = || {
+        ["gtk-4.0/settings.ini", "gtk-3.0/settings.ini"]
+            .into_iter()
+            .flat_map(config_files)
+            .find_map(|file| keyfile_value(&file, "Settings", "gtk-icon-theme-name"))
+    };
+    let kde_theme =
+        || config_files("kdeglobals").find_map(|file| keyfile_value(&file, "Icons", "Theme"));
+    // The COSMIC setting is stored as a RON string.
+    let cosmic_theme = || {
+        config_files("cosmic/com.system76.CosmicTk/v1/icon_theme")
+            .find_map(|file| non_empty(unquote(file.trim())))
+    };
+    let xsettings_theme = || {
+        let xsettings_file = xsettings_file
+            .into_iter()
+            .filter_map(|path| std::fs::read_to_string(path).ok());
+
+        config_files("xsettingsd/xsettingsd.conf")
+            .chain(xsettings_file)
+            .find_map(|file| {
+                file.lines().find_map(|line| {
+                    let value = line.trim().strip_prefix("Net/IconThemeName")?;
+                    non_empty(unquote(value.trim()))
+                })
+            })
+    };
+
+    gtk_theme()
+        .or_else(kde_theme)
+        .or_else(cosmic_theme)
+        .or_else(xsettings_theme)
+}
+
+fn keyfile_dirs(dconf_db: &Path) -> impl Iterator<Item = PathBuf> {
+    read_dir_sorted(dconf_db)
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "d") && path.is_dir())
+}
+
+fn read_dir_sorted(dir: &Path) -> Vec<PathBuf> {
+    let mut entries = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect::<Vec<_>>();
+    entries.sort_unstable();
+    entries
+}
+
+// Get the value of a key in a group of an ini-like keyfile.
+fn keyfile_value(file: &str, group: &str, key: &str) -> Option<String> {
+    let mut in_group = false;
+    for line in file.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_group = name == group;
+        } else if in_group
+            && let Some((line_key, value)) = line.split_once('=')
+            && line_key.trim() == key
+        {
+            return non_empty(unquote(value.trim()));
+        }
+    }
+
+    None
+}
+
+// Strip the double or single quotes of GVariant and RON strings.
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .into_iter()
+        .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote))
+        .unwrap_or(value)
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::SettingsPaths;
+    use crate::temp_dir::TempDir;
+
+    struct Fixture(TempDir);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            Self(TempDir::new(&format!("settings-{name}")))
+        }
+
+        fn write(&self, path: &str, contents: &str) -> &Self {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+            self
+        }
+
+        fn paths(&self) -> SettingsPaths {
+            let path = |path: &str| self.0.join(path);
+            SettingsPaths {
+                config_home: Some(path("config")),
+                config_dirs: vec![path("etc/xdg")],
+                home: Some(path("home")),
+                dconf_db: path("etc/dconf/db"),
+            }
+        }
+    }
+
+    #[test]
+    fn should_read_gtk_settings_first() {
+        let fixture = Fixture::new("gtk");
+        fixture
+            .write(
+                "config/gtk-3.0/settings.ini",
+                "[Settings]\ngtk-theme-name = Adwaita\ngtk-icon-theme-name = Papirus-Dark\n",
+            )
+            .write("config/kdeglobals", "[Icons]\nTheme=breeze\n");
+
+        assert_eq!(
+            fixture.paths().current_theme().as_deref(),
+            Some("Papirus-Dark")
+        );
+    }
+
+    #[test]
+    fn should_prefer_settings_of_the_user() {
+        let fixture = Fixture::new("user");
+        fixture
+            .write(
+                "etc/xdg/gtk-4.0/settings.ini",
+                "[Settings]\ngtk-icon-theme-name=Adwaita\n",
+            )
+            .write(
+                "config/gtk-3.0/settings.ini",
+                "[Settings]\ngtk-icon-theme-name=Papirus\n",
+            );
+
+        assert_eq!(fixture.paths().current_theme().as_deref(), Some("Papirus"));
+
+        fixture
+            .write("config/gtk-3.0/settings.ini", "[Settings]\n")
+            .write("config/kdeglobals", "[Icons]\nTheme=breeze\n");
+
+        assert_eq!(fixture.paths().current_theme().as_deref(), Some("breeze"));
+    }
+
+    #[test]
+    fn should_read_kde_icons_group() {
+        let fixture = Fixture::new("kde");
+        fixture.write(
+            "etc/xdg/kdeglobals",
+            "[General]\nTheme=wrong\n\n[Icons]\nTheme=breeze-dark\n",
+        );
+
+        assert_eq!(
+            fixture.paths().current_theme().as_deref(),
+            Some("breeze-dark")
+        );
+    }
+
+    #[test]
+    fn should_read_cosmic_config() {
+        let fixture = Fixture::new("cosmic");
+        fixture.write(
+            "config/cosmic/com.system76.CosmicTk/v1/icon_theme",
+            "\"Cosmic\"",
+        );
+
+        assert_eq!(fixture.paths().current_theme().as_deref(), Some("Cosmic"));
+    }
+
+    #[test]
+    fn should_read_xsettings_and_dconf_fallbacks() {
+        let fixture = Fixture::new("fallbacks");
+        fixture.write(
+            "etc/dconf/db/local.d/00-theme",
+            "[org/gnome/desktop/interface]\nicon-theme='Yaru'\n",
+        );
+
+        assert_eq!(fixture.paths().current_theme().as_deref(), Some("Yaru"));
+
+        fixture.write("home/.xsettingsd", "Net/IconThemeName \"elementary\"\n");
+
+        assert_eq!(
+            fixture.paths().current_theme().as_deref(),
+            Some("elementary")
+        );
+    }
+
+    #[test]
+    fn should_not_find_unconfigured_theme() {
+        let fixture = Fixture::new("empty");
+        fixture.write(
+            "config/gtk-3.0/settings.ini",
+            "[Settings]\ngtk-icon-theme-name=\n",
+        );
+
+        assert_eq!(fixture.paths().current_theme(), None);
+    }
+}
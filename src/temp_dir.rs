@@ -0,0 +1,57 @@
+//! Temporary directories of the tests.
+
+use std::ffi::OsStr;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty directory under the temporary directory, named after the test and the process,
+/// which is removed when dropped, even when the test fails.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "cosmic-freedesktop-icons-{name}-{}",
+            std::process::id()
+        ));
+        _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+/// Write a theme named `name` in `dir`, with a single `16x16/apps` directory.
+pub(crate) fn write_theme(dir: &Path, name: &str) {
+    std::fs::create_dir_all(dir.join("16x16/apps")).unwrap();
+    std::fs::write(
+        dir.join("index.theme"),
+        format!("[Icon Theme]\nName={name}\nDirectories=16x16/apps\n\n[16x16/apps]\nSize=16\n"),
+    )
+    .unwrap();
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<OsStr> for TempDir {
+    fn as_ref(&self) -> &OsStr {
+        self.0.as_os_str()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -4,6 +4,7 @@ pub struct Directory<'a> {
     pub size: i16,
     pub scale: i16,
     pub type_: DirectoryType,
+    pub context: Option<Context>,
     pub maxsize: i16,
     pub minsize: i16,
     pub threshold: i16,
@@ -43,19 +44,14 @@ impl Directory<'_> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum DirectoryType {
     Fixed,
     Scalable,
+    #[default]
     Threshold,
 }
 
-impl Default for DirectoryType {
-    fn default() -> Self {
-        Self::Threshold
-    }
-}
-
 impl From<&[u8]> for DirectoryType {
     fn from(value: &[u8]) -> Self {
         match value[0] {
@@ -65,3 +61,64 @@ impl From<&[u8]> for DirectoryType {
         }
     }
 }
+
+/// The kind of icons stored in a theme directory, given by its `Context` key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Context {
+    Actions,
+    Animations,
+    Applications,
+    Categories,
+    Devices,
+    Emblems,
+    Emotes,
+    FileSystems,
+    International,
+    MimeTypes,
+    Places,
+    Status,
+    /// A context which is not defined by the specification, such as `Legacy` or `UI`.
+    Other(Box<str>),
+}
+
+impl From<&[u8]> for Context {
+    fn from(value: &[u8]) -> Self {
+        match value {
+            b"Actions" => Context::Actions,
+            b"Animations" => Context::Animations,
+            b"Applications" => Context::Applications,
+            b"Categories" => Context::Categories,
+            b"Devices" => Context::Devices,
+            b"Emblems" => Context::Emblems,
+            b"Emotes" => Context::Emotes,
+            b"FileSystems" => Context::FileSystems,
+            b"International" => Context::International,
+            b"MimeTypes" => Context::MimeTypes,
+            b"Places" => Context::Places,
+            b"Status" => Context::Status,
+            other => Context::Other(String::from_utf8_lossy(other).into()),
+        }
+    }
+}
+
+impl Context {
+    /// The value of the `Context` key of the directory.
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Context::Actions => "Actions",
+            Context::Animations => "Animations",
+            Context::Applications => "Applications",
+            Context::Categories => "Categories",
+            Context::Devices => "Devices",
+            Context::Emblems => "Emblems",
+            Context::Emotes => "Emotes",
+            Context::FileSystems => "FileSystems",
+            Context::International => "International",
+            Context::MimeTypes => "MimeTypes",
+            Context::Places => "Places",
+            Context::Status => "Status",
+            Context::Other(context) => context,
+        }
+    }
+}
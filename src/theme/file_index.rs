@@ -0,0 +1,151 @@
+//! An in-memory index of the icon files of theme directories, replacing the probing of each
+//! directory and extension with map lookups.
+
+use crate::theme::gtk_cache::extension_flag;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The files of the directories of a theme, indexed on first use.
+#[derive(Debug)]
+pub(crate) struct FileIndex {
+    // How long an indexed directory is trusted before checking its modification time again.
+    interval: Duration,
+    directories: RwLock<BTreeMap<Box<str>, IndexedDirectory>>,
+}
+
+#[derive(Debug)]
+struct IndexedDirectory {
+    modified: Option<SystemTime>,
+    checked: Instant,
+    // The icon names found in the directory, with the flags of their extensions.
+    icons: BTreeMap<Box<str>, u16>,
+}
+
+impl FileIndex {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            directories: RwLock::default(),
+        }
+    }
+
+    /// Whether the directory of the theme holds the icon with the given extension, such as `.png`.
+    ///
+    /// The directory is read the first time, and read again when its modification time changed.
+    pub(crate) fn contains(
+        &self,
+        theme_dir: &Path,
+        directory: &str,
+        name: &str,
+        ext: &str,
+    ) -> bool {
+        let Some(flag) = extension_flag(ext) else {
+            return false;
+        };
+
+        if let Some(indexed) = self.directories.read().unwrap().get(directory)
+            && indexed.checked.elapsed() < self.interval
+        {
+            return indexed.has(name, flag);
+        }
+
+        let path = theme_dir.join(directory);
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        let mut directories = self.directories.write().unwrap();
+        match directories.get_mut(directory) {
+            Some(indexed) if modified.is_some() && indexed.modified == modified => {
+                indexed.checked = Instant::now();
+                indexed.has(name, flag)
+            }
+            _ => {
+                tracing::debug!(?path, "indexing icon directory");
+                let indexed = IndexedDirectory::read(&path, modified);
+                let found = indexed.has(name, flag);
+                directories.insert(directory.into(), indexed);
+                found
+            }
+        }
+    }
+
+    /// Read the directory again when it is next searched.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn remove(&self, directory: &str) {
+        self.directories.write().unwrap().remove(directory);
+    }
+}
+
+impl IndexedDirectory {
+    fn read(path: &Path, modified: Option<SystemTime>) -> Self {
+        let mut icons = BTreeMap::<Box<str>, u16>::new();
+
+        for entry in path.read_dir().into_iter().flatten().flatten() {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                continue;
+            }
+
+            let file_name = entry.file_name();
+            let Some((name, flag)) = file_name.to_str().and_then(|file_name| {
+                let (name, ext) = file_name.split_at(file_name.rfind('.')?);
+                Some((name, extension_flag(ext)?))
+            }) else {
+                continue;
+            };
+
+            *icons.entry(name.into()).or_default() |= flag;
+        }
+
+        Self {
+            modified,
+            checked: Instant::now(),
+            icons,
+        }
+    }
+
+    fn has(&self, name: &str, flag: u16) -> bool {
+        self.icons.get(name).is_some_and(|flags| flags & flag != 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FileIndex;
+    use crate::temp_dir::TempDir;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn should_index_directories() {
+        let root = TempDir::new("file-index");
+        std::fs::create_dir_all(root.join("16x16/apps/folder.png")).unwrap();
+        std::fs::write(root.join("16x16/apps/app.png"), "").unwrap();
+        std::fs::write(root.join("16x16/apps/app.symbolic.svg"), "").unwrap();
+
+        let index = FileIndex::new(Duration::from_secs(60));
+        assert!(index.contains(&root, "16x16/apps", "app", ".png"));
+        assert!(index.contains(&root, "16x16/apps", "app.symbolic", ".svg"));
+        assert!(!index.contains(&root, "16x16/apps", "app", ".svg"));
+        assert!(!index.contains(&root, "16x16/apps", "folder", ".png"));
+        assert!(!index.contains(&root, "32x32/apps", "app", ".png"));
+
+        // A modified directory is read again once the index is not trusted anymore.
+        std::fs::write(root.join("16x16/apps/new.svg"), "").unwrap();
+        std::fs::File::open(root.join("16x16/apps"))
+            .and_then(|dir| dir.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .unwrap();
+        assert!(!index.contains(&root, "16x16/apps", "new", ".svg"));
+        for indexed in index.directories.write().unwrap().values_mut() {
+            indexed.checked -= index.interval;
+        }
+        assert!(index.contains(&root, "16x16/apps", "new", ".svg"));
+
+        // A removed directory is read again when next searched.
+        std::fs::write(root.join("16x16/apps/removed.svg"), "").unwrap();
+        assert!(!index.contains(&root, "16x16/apps", "removed", ".svg"));
+        index.remove("16x16/apps");
+        assert!(index.contains(&root, "16x16/apps", "removed", ".svg"));
+    }
+}
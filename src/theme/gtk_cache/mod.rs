@@ -0,0 +1,434 @@
+//! Reader for the `icon-theme.cache` files generated by `gtk-update-icon-cache`.
+//!
+//! The cache maps each icon name of a theme to the directories holding the icon, along with
+//! the file extensions available there. All the numbers are big-endian:
+//!
+//! ```text
+//! Header:    u16 major version (1), u16 minor version (0), u32 hash offset, u32 directory list offset
+//! Hash:      u32 bucket count, u32 icon offset per bucket (0xffffffff when empty)
+//! Icon:      u32 next icon offset in the bucket, u32 name offset, u32 image list offset
+//! ImageList: u32 image count, then u16 directory index, u16 flags, u32 image data offset per image
+//! DirList:   u32 directory count, u32 directory name offset per directory
+//! ```
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+mod write;
+
+pub use write::write_icon_theme_cache;
+
+pub(crate) const FILE_NAME: &str = "icon-theme.cache";
+
+pub(crate) const MAJOR_VERSION: u16 = 1;
+pub(crate) const MINOR_VERSION: u16 = 0;
+
+pub(crate) const HAS_SUFFIX_XPM: u16 = 1 << 0;
+pub(crate) const HAS_SUFFIX_SVG: u16 = 1 << 1;
+pub(crate) const HAS_SUFFIX_PNG: u16 = 1 << 2;
+
+// The offset of empty buckets and of the last icon of a bucket.
+pub(crate) const NONE: u32 = u32::MAX;
+
+/// The memory mapped `icon-theme.cache` of a theme directory.
+#[derive(Debug)]
+pub(crate) struct GtkCache {
+    data: Mmap,
+}
+
+/// The `icon-theme.cache` of a theme directory, whose state is checked again at most once per
+/// interval. The file is opened again when it was replaced, and ignored while it is older than
+/// the theme directory or one of its icon directories, which are then probed instead.
+#[derive(Debug)]
+pub(crate) struct CacheFile {
+    interval: Duration,
+    state: RwLock<Option<CacheState>>,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    checked: Instant,
+    // The modification time of the file when it was opened.
+    modified: Option<SystemTime>,
+    cache: Option<Arc<GtkCache>>,
+    up_to_date: bool,
+}
+
+/// A directory holding an icon, with the extensions of the icon files found there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CachedImage<'a> {
+    pub directory: &'a str,
+    pub flags: u16,
+}
+
+impl CacheFile {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: RwLock::default(),
+        }
+    }
+
+    /// The cache of the theme directory, if it is valid and up to date.
+    pub(crate) fn get(
+        &self,
+        theme_dir: &Path,
+        icon_directories: impl FnOnce() -> Vec<PathBuf>,
+    ) -> Option<Arc<GtkCache>> {
+        if let Some(state) = &*self.state.read().unwrap()
+            && state.checked.elapsed() < self.interval
+        {
+            return state.cache.clone().filter(|_| state.up_to_date);
+        }
+
+        let path = theme_dir.join(FILE_NAME);
+        let file_modified = modified(&path);
+        let mut state = self.state.write().unwrap();
+        let cache = match state.take() {
+            Some(state) if state.modified == file_modified => state.cache,
+            _ => GtkCache::open(theme_dir).map(Arc::new),
+        };
+
+        let up_to_date = cache.is_some()
+            && std::iter::once(theme_dir.to_owned())
+                .chain(icon_directories())
+                .all(|directory| modified(&directory) <= file_modified);
+        if cache.is_some() && !up_to_date {
+            tracing::debug!(?path, "ignoring outdated icon theme cache");
+        }
+
+        *state = Some(CacheState {
+            checked: Instant::now(),
+            modified: file_modified,
+            cache: cache.clone(),
+            up_to_date,
+        });
+        cache.filter(|_| up_to_date)
+    }
+
+    /// Check the file again when it is next used.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn forget(&self) {
+        *self.state.write().unwrap() = None;
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl GtkCache {
+    /// Open the cache of the theme directory, if it is valid.
+    pub(crate) fn open(theme_dir: &Path) -> Option<Self> {
+        let path = theme_dir.join(FILE_NAME);
+        let file = File::open(&path).ok()?;
+        let data = unsafe { Mmap::map(&file) }.ok()?;
+        if !CacheView(&data).is_valid() {
+            tracing::warn!(?path, "ignoring invalid icon theme cache");
+            return None;
+        }
+
+        Some(Self { data })
+    }
+
+    /// The directories holding the given icon, in the order of the cache.
+    pub(crate) fn lookup(&self, name: &str) -> Vec<CachedImage<'_>> {
+        CacheView(&self.data).lookup(name)
+    }
+}
+
+impl CachedImage<'_> {
+    /// Whether the icon is found in the given directory with the given extension, such as `.png`.
+    pub(crate) fn has(&self, directory: &str, extension: &str) -> bool {
+        extension_flag(extension)
+            .is_some_and(|flag| self.directory == directory && self.flags & flag != 0)
+    }
+}
+
+/// The flag of an icon file extension, such as `.png`.
+pub(crate) fn extension_flag(extension: &str) -> Option<u16> {
+    match extension {
+        ".png" => Some(HAS_SUFFIX_PNG),
+        ".svg" => Some(HAS_SUFFIX_SVG),
+        ".xpm" => Some(HAS_SUFFIX_XPM),
+        _ => None,
+    }
+}
+
+// Bounds-checked accessors to the content of a cache.
+#[derive(Clone, Copy)]
+struct CacheView<'a>(&'a [u8]);
+
+impl<'a> CacheView<'a> {
+    fn bytes<const N: usize>(self, offset: u32) -> Option<[u8; N]> {
+        let offset = offset as usize;
+        self.0.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(self, offset: u32) -> Option<u16> {
+        self.bytes(offset).map(u16::from_be_bytes)
+    }
+
+    fn u32(self, offset: u32) -> Option<u32> {
+        self.bytes(offset).map(u32::from_be_bytes)
+    }
+
+    // The null-terminated string at the given offset.
+    fn str(self, offset: u32) -> Option<&'a [u8]> {
+        let string = self.0.get(offset as usize..)?;
+        Some(&string[..memchr::memchr(0, string)?])
+    }
+
+    fn hash_offset(self) -> Option<u32> {
+        self.u32(4)
+    }
+
+    fn directory_list_offset(self) -> Option<u32> {
+        self.u32(8)
+    }
+
+    fn is_valid(self) -> bool {
+        self.u16(0) == Some(MAJOR_VERSION)
+            && self.u16(2) == Some(MINOR_VERSION)
+            && self
+                .hash_offset()
+                .and_then(|offset| self.u32(offset))
+                .is_some_and(|n| n > 0)
+            && self
+                .directory_list_offset()
+                .and_then(|offset| self.u32(offset))
+                .is_some()
+    }
+
+    fn directory(self, index: u16) -> Option<&'a str> {
+        let offset = self.directory_list_offset()?;
+        if index as u32 >= self.u32(offset)? {
+            return None;
+        }
+
+        let name = self.str(self.u32(offset + 4 + 4 * index as u32)?)?;
+        std::str::from_utf8(name).ok()
+    }
+
+    fn lookup(self, name: &str) -> Vec<CachedImage<'a>> {
+        self.find_icon(name)
+            .and_then(|image_list| self.images(image_list))
+            .unwrap_or_default()
+    }
+
+    // The offset of the image list of the icon.
+    fn find_icon(self, name: &str) -> Option<u32> {
+        let hash_offset = self.hash_offset()?;
+        let buckets = self.u32(hash_offset)?;
+        let bucket = icon_name_hash(name.as_bytes()) % buckets;
+        let mut icon = self.u32(hash_offset.checked_add(4 + 4 * bucket)?)?;
+
+        // Each icon takes 12 bytes, which bounds the length of a chain in a corrupted cache.
+        for _ in 0..self.0.len() / 12 {
+            if icon == NONE {
+                return None;
+            }
+
+            if self.str(self.u32(icon.checked_add(4)?)?)? == name.as_bytes() {
+                return self.u32(icon.checked_add(8)?);
+            }
+
+            icon = self.u32(icon)?;
+        }
+
+        None
+    }
+
+    fn images(self, image_list: u32) -> Option<Vec<CachedImage<'a>>> {
+        let count = self.u32(image_list)?;
+        (0..count)
+            .map(|n| {
+                let image = image_list.checked_add(4 + 8 * n)?;
+                Some(CachedImage {
+                    directory: self.directory(self.u16(image)?)?,
+                    flags: self.u16(image + 2)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The hash of an icon name, computed over signed chars like GTK does.
+pub(crate) fn icon_name_hash(name: &[u8]) -> u32 {
+    let mut chars = name.iter().map(|&char| char as i8 as u32);
+    let Some(first) = chars.next() else {
+        return 0;
+    };
+
+    chars.fold(first, |hash, char| {
+        (hash << 5).wrapping_sub(hash).wrapping_add(char)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheView, CachedImage, GtkCache, HAS_SUFFIX_PNG, HAS_SUFFIX_SVG, NONE};
+    use std::path::Path;
+
+    enum Field {
+        U16(u16),
+        U32(u32),
+        Str(&'static str),
+    }
+
+    // A cache holding `a` in `16x16/apps` and `scalable/apps`, and `c` in `16x16/apps`.
+    fn cache() -> Vec<u8> {
+        use Field::*;
+
+        #[rustfmt::skip]
+        let fields = [
+            // Header
+            U16(1), U16(0), U32(12), U32(80),
+            // Hash at 12, `a` and `c` both belong to the second bucket
+            U32(2), U32(NONE), U32(24),
+            // Icons `a` at 24 and `c` at 36
+            U32(36), U32(92), U32(48),
+            U32(NONE), U32(94), U32(68),
+            // Image lists at 48 and 68
+            U32(2), U16(0), U16(HAS_SUFFIX_PNG), U32(0), U16(1), U16(HAS_SUFFIX_SVG), U32(0),
+            U32(1), U16(0), U16(HAS_SUFFIX_PNG), U32(0),
+            // Directory list at 80, and strings at 92
+            U32(2), U32(96), U32(107),
+            Str("a"), Str("c"), Str("16x16/apps"), Str("scalable/apps"),
+        ];
+
+        let mut data = Vec::new();
+        for field in fields {
+            match field {
+                U16(n) => data.extend(n.to_be_bytes()),
+                U32(n) => data.extend(n.to_be_bytes()),
+                Str(string) => data.extend(string.bytes().chain([0])),
+            }
+        }
+
+        data
+    }
+
+    #[test]
+    fn should_hash_like_gtk() {
+        assert_eq!(super::icon_name_hash(b"a"), 97);
+        assert_eq!(super::icon_name_hash(b"ab"), 97 * 31 + 98);
+        assert_eq!(
+            super::icon_name_hash("é".as_bytes()),
+            (-61i32 as u32).wrapping_mul(31).wrapping_sub(87)
+        );
+    }
+
+    #[test]
+    fn should_lookup_cached_icons() {
+        let data = cache();
+        let view = CacheView(&data);
+
+        assert!(view.is_valid());
+        assert_eq!(
+            view.lookup("a"),
+            [
+                CachedImage {
+                    directory: "16x16/apps",
+                    flags: HAS_SUFFIX_PNG
+                },
+                CachedImage {
+                    directory: "scalable/apps",
+                    flags: HAS_SUFFIX_SVG
+                },
+            ]
+        );
+        assert!(view.lookup("a")[1].has("scalable/apps", ".svg"));
+        assert!(!view.lookup("a")[1].has("scalable/apps", ".png"));
+        assert!(view.lookup("c")[0].has("16x16/apps", ".png"));
+        assert_eq!(view.lookup("b"), []);
+        assert!(!CacheView(&data[..40]).is_valid());
+    }
+
+    #[test]
+    fn should_only_use_up_to_date_cache() {
+        use crate::theme::Theme;
+        use std::time::{Duration, SystemTime};
+
+        let root = crate::temp_dir::TempDir::new("gtk-cache");
+        crate::temp_dir::write_theme(&root, "Cached");
+        std::fs::write(root.join(super::FILE_NAME), cache()).unwrap();
+
+        // The cached icon is not probed for.
+        let theme = Theme::new(root.to_path_buf(), root.join("index.theme"));
+        let icon = theme.try_get_icon("a", 16, 1, false, None);
+        assert_eq!(
+            icon.map(|icon| icon.path),
+            Some(root.join("16x16/apps/a.png"))
+        );
+
+        // An outdated cache is ignored.
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::open(&root)
+            .and_then(|dir| dir.set_modified(modified))
+            .unwrap();
+        let theme = Theme::new(root.to_path_buf(), root.join("index.theme"));
+        let icon = theme.try_get_icon("a", 16, 1, false, None);
+
+        assert_eq!(icon, None);
+    }
+
+    #[test]
+    fn should_check_cache_again() {
+        use crate::theme::Theme;
+        use std::time::{Duration, SystemTime};
+
+        let root = crate::temp_dir::TempDir::new("gtk-cache-check");
+        crate::temp_dir::write_theme(&root, "Cached");
+        let replace_cache = || {
+            let tmp_path = root.join("cache.tmp");
+            std::fs::write(&tmp_path, cache()).unwrap();
+            std::fs::rename(&tmp_path, root.join(super::FILE_NAME)).unwrap();
+        };
+        replace_cache();
+
+        let mut theme = Theme::new(root.to_path_buf(), root.join("index.theme"));
+        theme.set_revalidation_interval(Duration::ZERO);
+        let find = |name| {
+            theme
+                .try_get_icon(name, 16, 1, false, None)
+                .map(|icon| icon.path)
+        };
+        assert_eq!(find("a"), Some(root.join("16x16/apps/a.png")));
+
+        // The directories are probed while the cache is older than one of them.
+        std::fs::write(root.join("16x16/apps/b.png"), b"").unwrap();
+        std::fs::File::open(root.join("16x16/apps"))
+            .and_then(|dir| dir.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(find("a"), None);
+        assert_eq!(find("b"), Some(root.join("16x16/apps/b.png")));
+
+        // A replaced cache is opened again.
+        std::fs::File::open(root.join("16x16/apps"))
+            .and_then(|dir| dir.set_modified(SystemTime::now() - Duration::from_secs(60)))
+            .unwrap();
+        replace_cache();
+        assert_eq!(find("a"), Some(root.join("16x16/apps/a.png")));
+        assert_eq!(find("b"), None);
+    }
+
+    #[test]
+    fn should_read_system_cache() {
+        let theme = Path::new("/usr/share/icons/Adwaita");
+        let Some(cache) = GtkCache::open(theme) else {
+            return;
+        };
+
+        let images = cache.lookup("edit-delete-symbolic");
+        assert!(!images.is_empty(), "Is the Adwaita icon theme installed?");
+        for image in images {
+            assert!(theme.join(image.directory).is_dir());
+        }
+    }
+}
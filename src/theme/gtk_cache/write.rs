@@ -0,0 +1,457 @@
+//! Writer for `icon-theme.cache` files, producing the same bytes as `gtk-update-icon-cache --index-only`.
+//!
+//! GTK walks the theme depth-first with the entries of each directory sorted by name, and numbers
+//! the directories holding images in that order. The icons of each bucket are then laid out in the
+//! iteration order of GLib's `GHashTable`, which is modelled below.
+
+use super::{
+    FILE_NAME, HAS_SUFFIX_PNG, HAS_SUFFIX_SVG, HAS_SUFFIX_XPM, MAJOR_VERSION, MINOR_VERSION, NONE,
+    icon_name_hash,
+};
+use crate::theme::{Theme, read_ini_theme};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+const HASH_OFFSET: u32 = 12;
+
+// The bucket counts used by GTK, from `g_spaced_primes_closest`.
+const SPACED_PRIMES: [u32; 34] = [
+    11, 19, 37, 73, 109, 163, 251, 367, 557, 823, 1237, 1861, 2777, 4177, 6247, 9371, 14057, 21089,
+    31627, 47431, 71143, 106721, 160073, 240101, 360163, 540217, 810343, 1215497, 1823231, 2734867,
+    4102283, 6153409, 9230113, 13845163,
+];
+
+/// Write the `icon-theme.cache` file of a theme directory, like `gtk-update-icon-cache` does.
+///
+/// The cache covers the directories listed by the `index.theme` file of the theme, which are the
+/// ones searched by lookups. It is written atomically, and the modification time of the theme
+/// directory is set to the one of the cache so that readers consider it up to date.
+///
+/// ## Example
+/// ```rust,no_run
+/// # fn main() -> std::io::Result<()> {
+/// use cosmic_freedesktop_icons::write_icon_theme_cache;
+///
+/// write_icon_theme_cache("/usr/share/icons/hicolor")?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_icon_theme_cache(theme_dir: impl AsRef<Path>) -> io::Result<()> {
+    let theme_dir = theme_dir.as_ref();
+    let index = theme_dir.join("index.theme");
+    let file = read_ini_theme(&index)?;
+    let theme = Theme::new(theme_dir.to_owned(), index);
+    let directories = theme
+        .get_all_directories(&file)
+        .map(|directory| directory.name)
+        .collect();
+    let data = build(theme_dir, directories)?;
+
+    let path = theme_dir.join(FILE_NAME);
+    let tmp_path = theme_dir.join(format!(".{FILE_NAME}"));
+    if let Err(error) = fs::write(&tmp_path, data).and_then(|()| fs::rename(&tmp_path, &path)) {
+        _ = fs::remove_file(&tmp_path);
+        return Err(error);
+    }
+
+    let modified = fs::metadata(&path)?.modified()?;
+    File::open(theme_dir)?.set_modified(modified)
+}
+
+// The content of the cache of the given theme directories.
+fn build(theme_dir: &Path, mut directories: Vec<&str>) -> io::Result<Vec<u8>> {
+    directories.sort_by(|a, b| a.split('/').cmp(b.split('/')));
+    directories.dedup();
+
+    let mut files = GHashTable::default();
+    let mut images = BTreeMap::<Vec<u8>, Vec<(u16, u16)>>::new();
+    let mut cached_directories = Vec::new();
+
+    for directory in directories {
+        let path = theme_dir.join(directory);
+        let Ok(entries) = fs::read_dir(&path) else {
+            continue;
+        };
+
+        let mut entries = entries
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        let mut directory_files = GHashTable::default();
+        let mut flags = BTreeMap::<&[u8], u16>::new();
+        for entry in &entries {
+            let name = entry.as_bytes();
+            let Some(dot) = memchr::memrchr(b'.', name) else {
+                continue;
+            };
+
+            let flag = match &name[dot..] {
+                b".png" => HAS_SUFFIX_PNG,
+                b".svg" => HAS_SUFFIX_SVG,
+                b".xpm" => HAS_SUFFIX_XPM,
+                _ => continue,
+            };
+
+            if !path.join(entry).is_file() {
+                continue;
+            }
+
+            directory_files.insert(&name[..dot]);
+            *flags.entry(&name[..dot]).or_default() |= flag;
+        }
+
+        if directory_files.len() == 0 {
+            continue;
+        }
+
+        let index = cached_directories.len() as u16;
+        cached_directories.push(directory);
+        // The images of the last directories come first.
+        for name in directory_files.keys() {
+            files.insert(name);
+            images
+                .entry(name.to_vec())
+                .or_default()
+                .insert(0, (index, flags[name]));
+        }
+    }
+
+    // GTK prepends the icons to their bucket, in the order of its hash table.
+    let bucket_count = SPACED_PRIMES
+        .into_iter()
+        .find(|&prime| prime as usize > files.len() / 3)
+        .unwrap_or(SPACED_PRIMES[SPACED_PRIMES.len() - 1]);
+    let mut buckets = vec![Vec::new(); bucket_count as usize];
+    for name in files.keys() {
+        buckets[(icon_name_hash(name) % bucket_count) as usize].insert(0, name);
+    }
+
+    let mut data = Vec::new();
+    put_u16(&mut data, MAJOR_VERSION);
+    put_u16(&mut data, MINOR_VERSION);
+    put_u32(&mut data, HASH_OFFSET);
+    put_u32(&mut data, 0);
+    put_u32(&mut data, bucket_count);
+    let bucket_offsets = data.len();
+    data.resize(bucket_offsets + 4 * bucket_count as usize, 0);
+
+    for (bucket, icons) in buckets.iter().enumerate() {
+        let offset = if icons.is_empty() {
+            NONE
+        } else {
+            data.len() as u32
+        };
+        data[bucket_offsets + 4 * bucket..][..4].copy_from_slice(&offset.to_be_bytes());
+
+        for (n, name) in icons.iter().enumerate() {
+            let images = &images[*name];
+            let name_offset = data.len() as u32 + 12;
+            let image_list_offset = name_offset + padded_len(name);
+            let next_offset = if n + 1 < icons.len() {
+                image_list_offset + 4 + 8 * images.len() as u32
+            } else {
+                NONE
+            };
+
+            put_u32(&mut data, next_offset);
+            put_u32(&mut data, name_offset);
+            put_u32(&mut data, image_list_offset);
+            put_str(&mut data, name);
+            put_u32(&mut data, images.len() as u32);
+            for &(directory, flags) in images {
+                put_u16(&mut data, directory);
+                put_u16(&mut data, flags);
+                // No image data.
+                put_u32(&mut data, 0);
+            }
+        }
+    }
+
+    let directory_list_offset = data.len() as u32;
+    data[8..12].copy_from_slice(&directory_list_offset.to_be_bytes());
+    put_u32(&mut data, cached_directories.len() as u32);
+    let mut name_offset = directory_list_offset + 4 + 4 * cached_directories.len() as u32;
+    for directory in &cached_directories {
+        put_u32(&mut data, name_offset);
+        name_offset += padded_len(directory.as_bytes());
+    }
+    for directory in &cached_directories {
+        put_str(&mut data, directory.as_bytes());
+    }
+
+    Ok(data)
+}
+
+fn put_u16(data: &mut Vec<u8>, n: u16) {
+    data.extend(n.to_be_bytes());
+}
+
+fn put_u32(data: &mut Vec<u8>, n: u32) {
+    data.extend(n.to_be_bytes());
+}
+
+// Strings are null-terminated and padded to 4 bytes.
+fn put_str(data: &mut Vec<u8>, string: &[u8]) {
+    let len = data.len();
+    data.extend(string);
+    data.resize(len + padded_len(string) as usize, 0);
+}
+
+fn padded_len(string: &[u8]) -> u32 {
+    (string.len() as u32 + 1).next_multiple_of(4)
+}
+
+const MIN_SHIFT: usize = 3;
+
+// The modulos used by `GHashTable` for each power of two size.
+const PRIME_MOD: [u32; 32] = [
+    1, 2, 3, 7, 13, 31, 61, 127, 251, 509, 1021, 2039, 4093, 8191, 16381, 32749, 65521, 131071,
+    262139, 524287, 1048573, 2097143, 4194301, 8388593, 16777213, 33554393, 67108859, 134217689,
+    268435399, 536870909, 1073741789, 2147483647,
+];
+
+// Marks unused nodes, as GLib reserves the hashes 0 and 1.
+const UNUSED: u32 = 0;
+
+// A model of a GLib `GHashTable` of strings which are only inserted, as of GLib 2.60.
+struct GHashTable {
+    hashes: Vec<u32>,
+    keys: Vec<Vec<u8>>,
+    modulo: u32,
+    nodes: usize,
+}
+
+impl Default for GHashTable {
+    fn default() -> Self {
+        Self {
+            hashes: vec![UNUSED; 1 << MIN_SHIFT],
+            keys: vec![Vec::new(); 1 << MIN_SHIFT],
+            modulo: PRIME_MOD[MIN_SHIFT],
+            nodes: 0,
+        }
+    }
+}
+
+impl GHashTable {
+    fn len(&self) -> usize {
+        self.nodes
+    }
+
+    // The keys in iteration order.
+    fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.hashes
+            .iter()
+            .zip(&self.keys)
+            .filter(|&(&hash, _)| hash != UNUSED)
+            .map(|(_, key)| key.as_slice())
+    }
+
+    fn index(&self, hash: u32) -> usize {
+        (hash.wrapping_mul(11) % self.modulo) as usize
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        let hash = g_str_hash(key).max(2);
+        let mask = self.hashes.len() - 1;
+        let mut index = self.index(hash);
+        let mut step = 0;
+
+        while self.hashes[index] != UNUSED {
+            if self.hashes[index] == hash && self.keys[index] == key {
+                return;
+            }
+
+            step += 1;
+            index = (index + step) & mask;
+        }
+
+        self.hashes[index] = hash;
+        self.keys[index] = key.to_vec();
+        self.nodes += 1;
+
+        if self.hashes.len() <= self.nodes + self.nodes / 16 {
+            self.grow();
+        }
+    }
+
+    // GLib grows the table in place, moving each node to its new place and then the node it
+    // evicts from there, until reaching an unused place.
+    fn grow(&mut self) {
+        let old_size = self.hashes.len();
+        let size = (self.nodes as f64 * 1.333) as usize;
+        let shift = ((usize::BITS - size.leading_zeros()) as usize).max(MIN_SHIFT);
+        let mask = (1 << shift) - 1;
+
+        self.modulo = PRIME_MOD[shift];
+        self.hashes.resize(1 << shift, UNUSED);
+        self.keys.resize(1 << shift, Vec::new());
+
+        let mut relocated = vec![false; 1 << shift];
+        for node in 0..old_size {
+            if self.hashes[node] == UNUSED || relocated[node] {
+                continue;
+            }
+
+            let mut hash = std::mem::replace(&mut self.hashes[node], UNUSED);
+            let mut key = std::mem::take(&mut self.keys[node]);
+            loop {
+                let mut index = self.index(hash);
+                let mut step = 0;
+                while relocated[index] {
+                    step += 1;
+                    index = (index + step) & mask;
+                }
+
+                relocated[index] = true;
+                let evicted = std::mem::replace(&mut self.hashes[index], hash);
+                key = std::mem::replace(&mut self.keys[index], key);
+                if evicted == UNUSED {
+                    break;
+                }
+
+                hash = evicted;
+            }
+        }
+    }
+}
+
+// The string hash of GLib, computed over signed chars.
+fn g_str_hash(key: &[u8]) -> u32 {
+    key.iter().fold(5381, |hash: u32, &char| {
+        (hash << 5)
+            .wrapping_add(hash)
+            .wrapping_add(char as i8 as u32)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{
+        CacheView, CachedImage, FILE_NAME, GtkCache, HAS_SUFFIX_PNG, HAS_SUFFIX_SVG,
+    };
+    use crate::temp_dir::TempDir;
+    use std::path::Path;
+    use std::process::Command;
+
+    // A theme with many icons, so that buckets hold several of them and the hash table grows.
+    fn theme(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+
+        let directories = [
+            "16x16/apps",
+            "16x16@2x/apps",
+            "scalable/apps",
+            "16x16-extra/apps",
+        ];
+        for directory in directories {
+            std::fs::create_dir_all(root.join(directory)).unwrap();
+        }
+        std::fs::write(
+            root.join("index.theme"),
+            format!(
+                "[Icon Theme]\nName=Written\nDirectories={}\n\n[16x16/apps]\nSize=16\n\n\
+                 [16x16@2x/apps]\nSize=16\nScale=2\n\n[scalable/apps]\nSize=16\nType=Scalable\n\n\
+                 [16x16-extra/apps]\nSize=16\n",
+                directories.join(",")
+            ),
+        )
+        .unwrap();
+
+        for n in 0..200 {
+            std::fs::write(root.join(format!("16x16/apps/icon-{n}.png")), "").unwrap();
+            if n % 3 == 0 {
+                std::fs::write(root.join(format!("scalable/apps/icon-{n}.svg")), "").unwrap();
+            }
+            if n % 7 == 0 {
+                std::fs::write(root.join(format!("16x16@2x/apps/icon-{n}.png")), "").unwrap();
+            }
+        }
+        std::fs::write(root.join("16x16/apps/icon-0.xpm"), "").unwrap();
+        std::fs::write(root.join("16x16-extra/apps/icon-1.png"), "").unwrap();
+        std::fs::write(root.join("16x16/apps/readme.txt"), "").unwrap();
+        std::fs::create_dir(root.join("16x16/apps/folder.png")).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn should_model_glib_hash_table() {
+        let mut table = super::GHashTable::default();
+        let keys = (0..100).map(|n| format!("key-{n}")).collect::<Vec<_>>();
+        for key in &keys {
+            table.insert(key.as_bytes());
+        }
+        table.insert(b"key-0");
+
+        let mut sorted = table.keys().collect::<Vec<_>>();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(table.len(), 100);
+        assert_eq!(sorted.len(), 100);
+        assert_eq!(super::g_str_hash(b""), 5381);
+        assert_eq!(super::g_str_hash(b"a"), 5381 * 33 + 97);
+    }
+
+    #[test]
+    fn should_read_written_cache() {
+        let root = theme("written-cache");
+        std::fs::write(root.join("16x16/apps/é-symbolic.png"), "").unwrap();
+        super::write_icon_theme_cache(&root).unwrap();
+        let cache = GtkCache::open(&root);
+        let data = std::fs::read(root.join(FILE_NAME)).unwrap();
+
+        let view = CacheView(&data);
+        assert!(view.is_valid());
+        assert!(cache.is_some());
+        assert_eq!(
+            view.lookup("icon-0"),
+            [
+                CachedImage {
+                    directory: "scalable/apps",
+                    flags: HAS_SUFFIX_SVG,
+                },
+                CachedImage {
+                    directory: "16x16@2x/apps",
+                    flags: HAS_SUFFIX_PNG,
+                },
+                CachedImage {
+                    directory: "16x16/apps",
+                    flags: HAS_SUFFIX_PNG | super::HAS_SUFFIX_XPM,
+                },
+            ]
+        );
+        assert_eq!(view.lookup("icon-199").len(), 1);
+        assert_eq!(view.lookup("é-symbolic").len(), 1);
+        assert_eq!(view.lookup("readme"), []);
+        assert_eq!(view.lookup("folder"), []);
+        assert_eq!(view.lookup("icon-200"), []);
+    }
+
+    #[test]
+    fn should_write_like_gtk() {
+        let root = theme("gtk-cache");
+        let status = Command::new("gtk-update-icon-cache")
+            .args(["--force", "--quiet", "--index-only"])
+            .arg(&root)
+            .status();
+        let Ok(status) = status else {
+            return;
+        };
+        assert!(status.success());
+
+        let expected = std::fs::read(root.join(FILE_NAME)).unwrap();
+        super::write_icon_theme_cache(&root).unwrap();
+        let data = std::fs::read(root.join(FILE_NAME)).unwrap();
+
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn should_require_index() {
+        assert!(super::write_icon_theme_cache(Path::new("/nonexistent")).is_err());
+    }
+}
@@ -1,32 +1,98 @@
-use crate::theme::directories::DirectoryType;
+use crate::theme::directories::Directory;
+use crate::theme::file_index::FileIndex;
+use crate::theme::gtk_cache::{CacheFile, GtkCache};
 use crate::theme::paths::ThemePath;
+use crate::{IconInfo, IconSource, ThemeDirectory};
 use memmap2::Mmap;
-pub(crate) use paths::BASE_PATHS;
+pub(crate) use paths::{base_path_candidates, system_base_path_candidates};
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::ops::ControlFlow;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod directories;
+mod file_index;
+pub(crate) mod gtk_cache;
 mod parse;
 mod paths;
 
-pub static THEMES: LazyLock<BTreeMap<Vec<u8>, Vec<Theme>>> = LazyLock::new(get_all_themes);
+pub use directories::{Context, DirectoryType};
+pub use gtk_cache::write_icon_theme_cache;
 
 #[inline]
 pub fn read_ini_theme(path: &Path) -> std::io::Result<Mmap> {
     std::fs::File::open(path).and_then(|file| unsafe { Mmap::map(&file) })
 }
 
+/// Metadata of an installed icon theme, read from its `index.theme` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThemeInfo {
+    /// The name of the theme directory, to be given to [`LookupBuilder::with_theme`](crate::LookupBuilder::with_theme).
+    pub id: String,
+    /// The display name of the theme, or its directory name if its index has no `Name`.
+    pub name: String,
+    pub comment: Option<String>,
+    /// The names of the parent themes, in order of preference.
+    pub inherits: Vec<String>,
+    /// The name of an icon representing the theme.
+    pub example: Option<String>,
+    /// Whether the theme should be hidden from users, when selecting a theme.
+    pub hidden: bool,
+    /// The subdirectories of the theme listed by its `Directories` and `ScaledDirectories` keys.
+    pub directories: Vec<String>,
+    /// The directories in which the theme is installed, in order of precedence.
+    pub paths: Vec<PathBuf>,
+}
+
 #[derive(Debug)]
 pub struct Theme {
     pub path: ThemePath,
     pub index: PathBuf,
+    gtk_cache: CacheFile,
+    file_index: Option<FileIndex>,
 }
 
 impl Theme {
+    pub(crate) fn new(path: PathBuf, index: PathBuf) -> Self {
+        Self {
+            path: ThemePath(path),
+            index,
+            gtk_cache: CacheFile::new(crate::registry::REVALIDATION_INTERVAL),
+            file_index: None,
+        }
+    }
+
+    // Check whether the `icon-theme.cache` of the theme is up to date at most once per interval.
+    pub(crate) fn set_revalidation_interval(&mut self, interval: Duration) {
+        self.gtk_cache = CacheFile::new(interval);
+    }
+
+    // Find the icon files with an in-memory index of the theme directories instead of probing,
+    // checking whether an indexed directory changed at most once per interval.
+    pub(crate) fn enable_file_index(&mut self, interval: Duration) {
+        self.file_index = Some(FileIndex::new(interval));
+    }
+
+    // Forget what is known of the files of a directory of the theme, such as `16x16/apps`.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn forget_directory(&self, directory: &str) {
+        self.forget_gtk_cache();
+        if let Some(index) = &self.file_index {
+            index.remove(directory);
+        }
+    }
+
+    // Check the `icon-theme.cache` of the theme again when it is next used.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    pub(crate) fn forget_gtk_cache(&self) {
+        self.gtk_cache.forget();
+    }
+
     #[inline]
     pub fn try_get_icon(
         &self,
@@ -34,9 +100,10 @@ impl Theme {
         size: u16,
         scale: u16,
         prefer_svg: bool,
-    ) -> Option<PathBuf> {
+        context: Option<&Context>,
+    ) -> Option<IconInfo> {
         let file = read_ini_theme(&self.index).ok()?;
-        self.try_get_icon_closest_size(file.as_ref(), name, size, scale, prefer_svg)
+        self.try_get_icon_closest_size(file.as_ref(), name, size, scale, prefer_svg, context)
     }
 
     #[inline]
@@ -47,38 +114,110 @@ impl Theme {
         size: u16,
         scale: u16,
         prefer_svg: bool,
-    ) -> Option<PathBuf> {
-        self.try_fold_icon_path(
-            self.closest_match_size(file, size, scale, prefer_svg),
-            name,
-            prefer_svg,
-        )
+        context: Option<&Context>,
+    ) -> Option<IconInfo> {
+        // Symbolic icons are preferably taken from the dedicated `symbolic` directories.
+        let symbolic = prefer_svg && is_symbolic_name(name);
+        let directories = self.closest_match_size(file, size, scale, prefer_svg, symbolic);
+        let directories = partition_context(directories, context);
+        let (path, directory) = directories
+            .iter()
+            .find_map(|directories| self.try_fold_icon_path(directories, name, prefer_svg))?;
+        IconInfo::new(path, IconSource::Theme(self.theme_directory(directory)))
+    }
+
+    /// Get every icon file matching the given name, sorted by preference,
+    /// along with its size distance.
+    pub fn get_all_icons(
+        &self,
+        name: &str,
+        size: u16,
+        scale: u16,
+        prefer_svg: bool,
+        context: Option<&Context>,
+    ) -> Vec<(IconInfo, u16)> {
+        let Ok(file) = read_ini_theme(&self.index) else {
+            return Vec::new();
+        };
+
+        let symbolic = prefer_svg && is_symbolic_name(name);
+        let directories = self.closest_match_size(file.as_ref(), size, scale, prefer_svg, symbolic);
+        let extensions = if prefer_svg {
+            [".svg", ".png", ".xpm"]
+        } else {
+            [".png", ".svg", ".xpm"]
+        };
+
+        let gtk_cache = self.gtk_cache();
+        let indexed = self.indexed_icon(name, gtk_cache.as_deref());
+        let mut name_buf = String::new();
+        partition_context(directories, context)
+            .iter()
+            .flat_map(|directories| {
+                extensions
+                    .into_iter()
+                    .flat_map(move |ext| directories.iter().map(move |directory| (ext, directory)))
+            })
+            .filter_map(|(ext, (directory, distance))| {
+                let mut path = self.path().join(directory.name);
+                if let Some(has_file) = &indexed {
+                    if !has_file(directory.name, ext) {
+                        return None;
+                    }
+                    path.push([name, ext].concat());
+                } else {
+                    name_buf.clear();
+                    if !try_build_icon_path(&mut path, &mut name_buf, name, ext) {
+                        return None;
+                    }
+                }
+
+                let source = IconSource::Theme(self.theme_directory(directory));
+                Some((IconInfo::new(path, source)?, *distance as u16))
+            })
+            .collect()
     }
 
     fn try_fold_icon_path<'a>(
         &self,
-        dir_names: Vec<(&'a str, i16, bool)>,
+        directories: &'a [(Directory<'a>, i16)],
         name: &str,
         prefer_svg: bool,
-    ) -> Option<PathBuf> {
+    ) -> Option<(PathBuf, &'a Directory<'a>)> {
         let extensions = if prefer_svg {
             [".svg", ".png", ".xpm"]
         } else {
             [".png", ".svg", ".xpm"]
         };
 
+        let gtk_cache = self.gtk_cache();
+        if let Some(has_file) = self.indexed_icon(name, gtk_cache.as_deref()) {
+            return extensions.into_iter().find_map(|ext| {
+                directories
+                    .iter()
+                    .find(|(directory, _)| has_file(directory.name, ext))
+                    .map(|(directory, _)| {
+                        (
+                            self.path().join(directory.name).join([name, ext].concat()),
+                            directory,
+                        )
+                    })
+            });
+        }
+
         extensions.into_iter().find_map(|ext| {
-            dir_names
+            directories
                 .iter()
                 .try_fold(
                     (self.path().clone(), String::new()),
-                    move |(mut path, mut name_buf), (dir_name, _, _)| {
-                        path.push(dir_name);
+                    move |(mut path, mut name_buf), (directory, _)| {
+                        path.push(directory.name);
                         if try_build_icon_path(&mut path, &mut name_buf, name, ext) {
-                            ControlFlow::Break(path)
+                            ControlFlow::Break((path, directory))
                         } else {
                             name_buf.clear();
-                            let components = dir_name
+                            let components = directory
+                                .name
                                 .as_bytes()
                                 .iter()
                                 .fold(2, |n, c| n + (*c == b'/') as u32)
@@ -96,41 +235,139 @@ impl Theme {
         })
     }
 
+    // Theme directories sorted by preference, along with their size distance.
     fn closest_match_size<'a>(
         &'a self,
         file: &'a [u8],
         size: u16,
         scale: u16,
         prefer_svg: bool,
-    ) -> Vec<(&'a str, i16, bool)> {
+        symbolic: bool,
+    ) -> Vec<(Directory<'a>, i16)> {
         let mut unsorted = self.get_all_directories(file).fold(
-            Vec::<(&'a str, i16, bool)>::new(),
+            Vec::<(Directory<'a>, i16)>::new(),
             |mut unsorted, directory| {
-                let is_scalable = matches!(directory.type_, DirectoryType::Scalable);
                 let distance = directory.directory_size_distance(size as i16, scale as i16);
-                unsorted.push((directory.name, distance.abs(), is_scalable));
+                unsorted.push((directory, distance.abs()));
                 unsorted
             },
         );
 
-        unsorted.sort_by(|a, b| {
+        unsorted.sort_by(|(a, a_distance), (b, b_distance)| {
+            let a_scalable = a.type_ == DirectoryType::Scalable;
+            let b_scalable = b.type_ == DirectoryType::Scalable;
             let ordering = if prefer_svg {
-                b.2.cmp(&a.2)
+                b_scalable.cmp(&a_scalable)
             } else {
-                a.2.cmp(&b.2)
+                a_scalable.cmp(&b_scalable)
             };
             match ordering {
-                Ordering::Equal => a.1.cmp(&b.1),
+                Ordering::Equal => a_distance.cmp(b_distance),
                 _ => ordering,
             }
         });
 
+        if symbolic {
+            unsorted.sort_by_key(|(directory, _)| !is_symbolic_directory(directory.name));
+        }
+
         unsorted
     }
 
+    fn theme_directory(&self, directory: &Directory) -> ThemeDirectory {
+        ThemeDirectory {
+            theme: self.name().into_owned(),
+            name: directory.name.to_owned(),
+            size: directory.size as u16,
+            scale: directory.scale as u16,
+            type_: directory.type_,
+            context: directory.context.clone(),
+        }
+    }
+
+    /// The name of the theme directory, such as `Adwaita`.
+    pub fn name(&self) -> Cow<'_, str> {
+        self.path()
+            .file_name()
+            .map(OsStr::to_string_lossy)
+            .unwrap_or_default()
+    }
+
     fn path(&self) -> &PathBuf {
         &self.path.0
     }
+
+    /// The paths of the icon directories of the theme.
+    pub(crate) fn icon_directories(&self) -> Vec<PathBuf> {
+        let Ok(file) = read_ini_theme(&self.index) else {
+            return Vec::new();
+        };
+
+        self.get_all_directories(file.as_ref())
+            .map(|directory| self.path().join(directory.name))
+            .collect()
+    }
+
+    // Whether the icon is found in a directory with an extension, according to the icon cache
+    // or to the file index of the theme. `None` when the directories have to be probed.
+    fn indexed_icon<'a>(
+        &'a self,
+        name: &'a str,
+        gtk_cache: Option<&'a GtkCache>,
+    ) -> Option<HasFile<'a>> {
+        if let Some(cache) = gtk_cache {
+            let cached = cache.lookup(name);
+            return Some(Box::new(move |directory, ext| {
+                cached.iter().any(|image| image.has(directory, ext))
+            }));
+        }
+
+        let index = self.file_index.as_ref()?;
+        Some(Box::new(move |directory, ext| {
+            index.contains(self.path(), directory, name, ext)
+        }))
+    }
+
+    // The `icon-theme.cache` of the theme directory, if it is valid and up to date.
+    fn gtk_cache(&self) -> Option<Arc<GtkCache>> {
+        self.gtk_cache.get(self.path(), || self.icon_directories())
+    }
+}
+
+// Whether an icon file is found in a directory, given the directory name and the extension.
+type HasFile<'a> = Box<dyn Fn(&str, &str) -> bool + 'a>;
+
+// Split the directories of the requested context from the others, to search them first.
+fn partition_context<'a>(
+    directories: Vec<(Directory<'a>, i16)>,
+    context: Option<&Context>,
+) -> [Vec<(Directory<'a>, i16)>; 2] {
+    match context {
+        Some(context) => {
+            let (matching, others) = directories
+                .into_iter()
+                .partition(|(directory, _)| directory.context.as_ref() == Some(context));
+            [matching, others]
+        }
+        None => [directories, Vec::new()],
+    }
+}
+
+pub(crate) const SYMBOLIC_SUFFIX: &str = "-symbolic";
+
+// Symbolic icon names, including their directional variants.
+pub(crate) fn is_symbolic_name(name: &str) -> bool {
+    name.strip_suffix("-rtl")
+        .or_else(|| name.strip_suffix("-ltr"))
+        .unwrap_or(name)
+        .ends_with(SYMBOLIC_SUFFIX)
+}
+
+// Directories such as `symbolic/actions` or `symbolic-up-to-32/status`.
+fn is_symbolic_directory(name: &str) -> bool {
+    name.split('/')
+        .next()
+        .is_some_and(|component| component.starts_with("symbolic"))
 }
 
 pub(super) fn try_build_icon_path<'a>(
@@ -152,13 +389,52 @@ fn try_build_ext(path: &mut PathBuf, name_buf: &mut String, name: &str, ext: &'s
     path.exists()
 }
 
+/// Resolve the names of the given theme and of the themes it inherits from, depth-first
+/// and in the order of their `Inherits` keys. Themes which are not installed are skipped,
+/// and each theme is only visited once, which breaks inheritance cycles.
+pub(crate) fn inheritance_chain<'a>(
+    themes: &'a BTreeMap<Vec<u8>, Vec<Theme>>,
+    name: &[u8],
+) -> Vec<&'a [u8]> {
+    fn visit<'a>(
+        themes: &'a BTreeMap<Vec<u8>, Vec<Theme>>,
+        name: &[u8],
+        chain: &mut Vec<&'a [u8]>,
+    ) {
+        let Some((name, instances)) = themes.get_key_value(name) else {
+            return;
+        };
+
+        if chain.contains(&name.as_slice()) {
+            return;
+        }
+
+        chain.push(name);
+
+        // A theme installed in several base paths may have an index in each of them.
+        for theme in instances {
+            let Ok(file) = read_ini_theme(&theme.index) else {
+                continue;
+            };
+
+            for parent in theme.inherits(file.as_ref()) {
+                visit(themes, parent, chain);
+            }
+        }
+    }
+
+    let mut chain = Vec::new();
+    visit(themes, name, &mut chain);
+    chain
+}
+
 // Iter through the base paths and get all theme directories
-pub(super) fn get_all_themes() -> BTreeMap<Vec<u8>, Vec<Theme>> {
+pub(crate) fn get_all_themes(base_paths: &[PathBuf]) -> BTreeMap<Vec<u8>, Vec<Theme>> {
     let mut icon_themes = BTreeMap::<Vec<u8>, Vec<_>>::new();
     let mut found_indices = BTreeMap::new();
     let mut to_revisit = Vec::new();
 
-    for theme_base_dir in BASE_PATHS.iter() {
+    for theme_base_dir in base_paths {
         let dir_iter = match theme_base_dir.read_dir() {
             Ok(dir) => dir,
             Err(why) => {
@@ -184,17 +460,23 @@ pub(super) fn get_all_themes() -> BTreeMap<Vec<u8>, Vec<Theme>> {
         }
     }
 
+    // Theme directories without an index precede the first index of the theme.
+    let mut revisited = BTreeMap::<Vec<u8>, Vec<_>>::new();
     for entry in to_revisit {
         let name = entry.file_name();
         let fallback_index = found_indices.get(&name);
         if let Some(theme) = Theme::from_path(entry.path(), fallback_index) {
-            icon_themes
+            revisited
                 .entry(name.as_bytes().to_owned())
                 .or_default()
                 .push(theme);
         }
     }
 
+    for (name, themes) in revisited {
+        icon_themes.entry(name).or_default().splice(0..0, themes);
+    }
+
     icon_themes
 }
 
@@ -213,40 +495,50 @@ impl Theme {
         index
             .cloned()
             .or_else(|| local_index_exists.then_some(path.clone()))
-            .map(|index| Theme {
-                path: ThemePath({
-                    path.pop();
-                    path
-                }),
-                index,
+            .map(|index| {
+                path.pop();
+                Theme::new(path, index)
             })
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::THEMES;
+    use crate::IconThemeRegistry;
+    use crate::temp_dir::TempDir;
+    use crate::theme::Theme;
     use speculoos::prelude::*;
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
     #[test]
     fn get_one_icon() {
-        let themes = THEMES.get(&b"Adwaita"[..]).unwrap();
+        let snapshot = IconThemeRegistry::global().snapshot();
+        let themes = snapshot.themes.get(&b"Adwaita"[..]).unwrap();
         println!(
             "{:?}",
             themes.iter().find_map(|t| {
                 let file = super::read_ini_theme(&t.index).ok()?;
-                t.try_get_icon_closest_size(file.as_ref(), "edit-delete-symbolic", 24, 1, false)
+                t.try_get_icon_closest_size(
+                    file.as_ref(),
+                    "edit-delete-symbolic",
+                    24,
+                    1,
+                    false,
+                    None,
+                )
             })
         );
     }
 
     #[test]
     fn should_get_png_first() {
-        let themes = THEMES.get(&b"hicolor"[..]).unwrap();
+        let snapshot = IconThemeRegistry::global().snapshot();
+        let themes = snapshot.themes.get(&b"hicolor"[..]).unwrap();
         let icon = themes.iter().find_map(|t| {
             let file = super::read_ini_theme(&t.index).ok()?;
-            t.try_get_icon_closest_size(file.as_ref(), "blueman", 22, 1, false)
+            t.try_get_icon_closest_size(file.as_ref(), "blueman", 22, 1, false, None)
+                .map(|icon| icon.path)
         });
         assert_that!(icon).is_some().is_equal_to(PathBuf::from(
             "/usr/share/icons/hicolor/22x22/apps/blueman.png",
@@ -255,10 +547,12 @@ mod test {
 
     #[test]
     fn should_get_png_first_92() {
-        let themes = THEMES.get(&b"hicolor"[..]).unwrap();
+        let snapshot = IconThemeRegistry::global().snapshot();
+        let themes = snapshot.themes.get(&b"hicolor"[..]).unwrap();
         let icon = themes.iter().find_map(|t| {
             let file = super::read_ini_theme(&t.index).ok()?;
-            t.try_get_icon_closest_size(file.as_ref(), "blueman", 92, 1, false)
+            t.try_get_icon_closest_size(file.as_ref(), "blueman", 92, 1, false, None)
+                .map(|icon| icon.path)
         });
         assert_that!(icon).is_some().is_equal_to(PathBuf::from(
             "/usr/share/icons/hicolor/96x96/apps/blueman.png",
@@ -267,10 +561,12 @@ mod test {
 
     #[test]
     fn should_get_svg_first() {
-        let themes = THEMES.get(&b"hicolor"[..]).unwrap();
+        let snapshot = IconThemeRegistry::global().snapshot();
+        let themes = snapshot.themes.get(&b"hicolor"[..]).unwrap();
         let icon = themes.iter().find_map(|t| {
             let file = super::read_ini_theme(&t.index).ok()?;
-            t.try_get_icon_closest_size(file.as_ref(), "blueman", 24, 1, true)
+            t.try_get_icon_closest_size(file.as_ref(), "blueman", 24, 1, true, None)
+                .map(|icon| icon.path)
         });
         assert_that!(icon).is_some().is_equal_to(PathBuf::from(
             "/usr/share/icons/hicolor/scalable/apps/blueman.svg",
@@ -279,13 +575,88 @@ mod test {
 
     #[test]
     fn should_get_svg_first_96() {
-        let themes = THEMES.get(&b"hicolor"[..]).unwrap();
+        let snapshot = IconThemeRegistry::global().snapshot();
+        let themes = snapshot.themes.get(&b"hicolor"[..]).unwrap();
         let icon = themes.iter().find_map(|t| {
             let file = super::read_ini_theme(&t.index).ok()?;
-            t.try_get_icon_closest_size(file.as_ref(), "blueman", 96, 1, true)
+            t.try_get_icon_closest_size(file.as_ref(), "blueman", 96, 1, true, None)
+                .map(|icon| icon.path)
         });
         assert_that!(icon).is_some().is_equal_to(PathBuf::from(
             "/usr/share/icons/hicolor/scalable/apps/blueman.svg",
         ));
     }
+
+    #[test]
+    fn should_resolve_inheritance_depth_first() {
+        let root = TempDir::new("inheritance");
+
+        let mut themes = BTreeMap::new();
+        for (name, inherits) in [
+            ("Papirus-Dark", "Papirus"),
+            ("Papirus", "breeze,Papirus-Dark"),
+            ("breeze", "hicolor, Adwaita"),
+            ("Adwaita", "hicolor"),
+            ("Humanity", "Papirus"),
+        ] {
+            let path = root.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            let index = path.join("index.theme");
+            std::fs::write(
+                &index,
+                format!("[Icon Theme]\nName={name}\nInherits={inherits}\n"),
+            )
+            .unwrap();
+            themes.insert(name.as_bytes().to_vec(), vec![Theme::new(path, index)]);
+        }
+
+        let chain = super::inheritance_chain(&themes, b"Papirus-Dark");
+
+        assert_eq!(
+            chain,
+            [&b"Papirus-Dark"[..], b"Papirus", b"breeze", b"Adwaita"]
+        );
+    }
+
+    #[test]
+    fn should_prefer_user_theme_directories() {
+        let root = TempDir::new("user-themes");
+        let user = root.join("home/.icons");
+        let system = root.join("usr/share/icons");
+        for dir in [
+            user.join("Foo"),
+            system.join("Foo"),
+            user.join("Bar"),
+            system.join("Bar"),
+        ] {
+            std::fs::create_dir_all(&dir).unwrap();
+        }
+        for index in [user.join("Foo"), system.join("Foo"), system.join("Bar")] {
+            std::fs::write(index.join("index.theme"), "[Icon Theme]\n").unwrap();
+        }
+
+        let themes = super::get_all_themes(&[user.clone(), system.clone()]);
+
+        let paths = |name: &[u8]| {
+            themes[name]
+                .iter()
+                .map(|theme| (theme.path.0.clone(), theme.index.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            paths(b"Foo"),
+            [
+                (user.join("Foo"), user.join("Foo/index.theme")),
+                (system.join("Foo"), user.join("Foo/index.theme")),
+            ]
+        );
+        assert_eq!(
+            paths(b"Bar"),
+            [
+                (user.join("Bar"), system.join("Bar/index.theme")),
+                (system.join("Bar"), system.join("Bar/index.theme")),
+            ]
+        );
+    }
 }
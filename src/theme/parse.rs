@@ -1,13 +1,23 @@
-use crate::theme::Theme;
-use crate::theme::directories::{Directory, DirectoryType};
+use crate::theme::directories::{Context, Directory, DirectoryType};
+use crate::theme::{Theme, ThemeInfo};
 use bstr::BStr;
+use std::collections::BTreeSet;
 
 impl Theme {
+    /// The directories of the theme: the sections listed by its `Directories` and
+    /// `ScaledDirectories` keys. Sections which are not listed are ignored, and so are
+    /// listed directories without a section. Themes listing no directory at all get
+    /// every section, as before.
     pub(super) fn get_all_directories<'a>(
         &'a self,
         file: &'a [u8],
     ) -> impl Iterator<Item = Directory<'a>> + 'a {
         let mut iterator = sections(file);
+        let mut listed = listed_directories(file);
+
+        if listed.is_none() {
+            tracing::debug!(theme = ?self.path.0, "no directories listed in index.theme, using all sections");
+        }
 
         std::iter::from_fn(move || {
             let mut is_icon_theme = false;
@@ -17,6 +27,7 @@ impl Theme {
             let mut min_size = None;
             let mut threshold = None;
             let mut scale = None;
+            let mut context = None;
             let mut dtype = DirectoryType::default();
 
             #[allow(clippy::while_let_on_iterator)]
@@ -31,6 +42,7 @@ impl Theme {
                             b"Size" => size = btoi::btoi(value).ok(),
                             b"Scale" => scale = btoi::btoi(value).ok(),
                             b"Type" => dtype = DirectoryType::from(value),
+                            b"Context" => context = Some(Context::from(value)),
                             b"MaxSize" => max_size = btoi::btoi(value).ok(),
                             b"MinSize" => min_size = btoi::btoi(value).ok(),
                             b"Threshold" => threshold = btoi::btoi(value).ok(),
@@ -49,6 +61,7 @@ impl Theme {
                         min_size = None;
                         threshold = None;
                         scale = None;
+                        context = None;
                         dtype = DirectoryType::default();
                     }
 
@@ -57,13 +70,24 @@ impl Theme {
                             continue;
                         }
 
-                        let size = size.take()?;
+                        if let Some(listed) = listed.as_mut()
+                            && !listed.remove(name.as_bytes())
+                        {
+                            tracing::debug!(theme = ?self.path.0, directory = name, "ignoring section not listed in Directories");
+                            continue;
+                        }
+
+                        let Some(size) = size.take() else {
+                            tracing::warn!(theme = ?self.path.0, directory = name, "ignoring directory without a Size");
+                            continue;
+                        };
 
                         return Some(Directory {
                             name,
                             size,
                             scale: scale.unwrap_or(1),
                             type_: dtype,
+                            context: context.take(),
                             maxsize: max_size.unwrap_or(size),
                             minsize: min_size.unwrap_or(size),
                             threshold: threshold.unwrap_or(2),
@@ -72,6 +96,10 @@ impl Theme {
                 }
             }
 
+            for name in listed.take().into_iter().flatten() {
+                tracing::warn!(theme = ?self.path.0, directory = %BStr::new(name), "ignoring listed directory without a section");
+            }
+
             None
         })
     }
@@ -83,12 +111,70 @@ impl Theme {
             .flat_map(|(_, parents)| {
                 BStr::new(parents)
                     .split(|&char| char == b',')
+                    .map(<[u8]>::trim_ascii)
                     // Filtering out 'hicolor' since we are going to fallback there anyway
-                    .filter(|parent| parent != &b"hicolor")
+                    .filter(|parent| !parent.is_empty() && parent != b"hicolor")
             })
     }
 }
 
+impl ThemeInfo {
+    // The metadata of the theme, named after its directory if its index has no `Name`.
+    pub(crate) fn parse(id: &str, themes: &[Theme]) -> Option<Self> {
+        let mut info = Self::parse_index(id, themes)?;
+        if info.name.is_empty() {
+            info.name = info.id.clone();
+        }
+
+        Some(info)
+    }
+
+    // The metadata of the theme, if its index has a `Name`.
+    pub(crate) fn parse_named(id: &str, themes: &[Theme]) -> Option<Self> {
+        Self::parse_index(id, themes).filter(|info| !info.name.is_empty())
+    }
+
+    fn parse_index(id: &str, themes: &[Theme]) -> Option<Self> {
+        let file = themes
+            .iter()
+            .find_map(|theme| super::read_ini_theme(&theme.index).ok())?;
+
+        let mut info = ThemeInfo {
+            id: id.to_owned(),
+            name: String::new(),
+            comment: None,
+            inherits: Vec::new(),
+            example: None,
+            hidden: false,
+            directories: Vec::new(),
+            paths: themes.iter().map(|theme| theme.path.0.clone()).collect(),
+        };
+
+        let string = |value: &[u8]| String::from_utf8_lossy(value).trim().to_owned();
+        let list = |value: &[u8]| {
+            BStr::new(value)
+                .split(|&char| char == b',')
+                .map(string)
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+
+        for (key, value) in icon_theme_section(file.as_ref()) {
+            match key {
+                b"Name" => info.name = string(value),
+                b"Comment" => info.comment = Some(string(value)),
+                b"Inherits" => info.inherits = list(value),
+                b"Example" => info.example = Some(string(value)),
+                b"Hidden" => info.hidden = value == b"true",
+                b"Directories" | b"ScaledDirectories" => info.directories.extend(list(value)),
+                _ => (),
+            }
+        }
+
+        Some(info)
+    }
+}
+
 #[derive(Debug)]
 enum DirectorySection<'a> {
     Property(&'a [u8], &'a [u8]),
@@ -159,6 +245,25 @@ fn sections(file: &[u8]) -> impl Iterator<Item = DirectorySection<'_>> {
     })
 }
 
+// The directories listed by the `Directories` and `ScaledDirectories` keys,
+// or `None` if the theme has neither of them.
+fn listed_directories(file: &[u8]) -> Option<BTreeSet<&[u8]>> {
+    let mut listed = None;
+
+    for (key, value) in icon_theme_section(file) {
+        if key == b"Directories" || key == b"ScaledDirectories" {
+            listed.get_or_insert_with(BTreeSet::new).extend(
+                value
+                    .split(|&char| char == b',')
+                    .map(<[u8]>::trim_ascii)
+                    .filter(|name| !name.is_empty()),
+            );
+        }
+    }
+
+    listed
+}
+
 fn icon_theme_section(file: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> + '_ {
     let mut found_table = false;
     let mut prev = 0;
@@ -201,7 +306,6 @@ fn icon_theme_section(file: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> + '_
 }
 
 #[cfg(test)]
-
 mod test {
     const ADWAITA_INDEX: &str = "[Icon Theme]
 Name=Adwaita\u{0020}
@@ -456,11 +560,82 @@ Type=Scalable";
         assert_eq!(iterator.next(), None);
     }
 
+    #[test]
+    fn directories() {
+        use crate::theme::{Context, DirectoryType, Theme};
+        use std::path::PathBuf;
+
+        let theme = Theme::new(PathBuf::new(), PathBuf::new());
+        let directories = theme
+            .get_all_directories(ADWAITA_INDEX.as_bytes())
+            .collect::<Vec<_>>();
+
+        assert_eq!(directories.len(), 34);
+        assert_eq!(directories[0].name, "16x16/actions");
+        assert_eq!(directories[0].context, Some(Context::Actions));
+        assert_eq!(directories[10].context, Some(Context::Other("UI".into())));
+        assert_eq!(directories[11].name, "scalable/devices");
+        assert_eq!(directories[11].type_, DirectoryType::Scalable);
+        assert_eq!(directories[11].minsize, 8);
+        assert_eq!(directories[11].maxsize, 512);
+    }
+
+    #[test]
+    fn should_only_get_listed_directories() {
+        use crate::theme::Theme;
+        use std::path::PathBuf;
+
+        const INDEX: &str = "[Icon Theme]
+Name=Stale
+Directories=16x16/apps, 32x32/apps,scalable/apps
+ScaledDirectories=16x16@2x/apps
+
+[16x16/apps]
+Size=16
+
+[24x24/apps]
+Size=24
+
+[32x32/apps]
+Size=32
+
+[16x16@2x/apps]
+Size=16
+Scale=2
+
+[X-KDE Extra]
+Foo=bar
+";
+
+        let theme = Theme::new(PathBuf::new(), PathBuf::new());
+        let directories = theme
+            .get_all_directories(INDEX.as_bytes())
+            .map(|directory| (directory.name, directory.scale))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            directories,
+            [("16x16/apps", 1), ("32x32/apps", 1), ("16x16@2x/apps", 2)]
+        );
+
+        let unlisted = INDEX
+            .lines()
+            .filter(|line| !line.contains("Directories="))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(theme.get_all_directories(unlisted.as_bytes()).count(), 4);
+    }
+
     #[test]
     #[cfg(feature = "local_tests")]
     fn should_get_theme_parents() {
         use speculoos::prelude::*;
-        for theme in crate::THEMES.get("Arc").unwrap() {
+        for theme in crate::IconThemeRegistry::global()
+            .snapshot()
+            .themes
+            .get(&b"Arc"[..])
+            .unwrap()
+        {
             let file = crate::theme::read_ini_theme(&theme.index).ok().unwrap();
             let file = std::str::from_utf8(file.as_ref()).ok().unwrap();
             let parents = theme.inherits(file);
@@ -1,33 +1,86 @@
 use std::path::PathBuf;
-use std::sync::LazyLock;
 use xdg::BaseDirectories;
 
-pub(crate) static BASE_PATHS: LazyLock<Vec<PathBuf>> = LazyLock::new(icon_theme_base_paths);
+// The system icon directories exported by Flatpak and Snap applications.
+const SYSTEM_APP_EXPORTS: [&str; 2] = [
+    "/var/lib/flatpak/exports/share/icons",
+    "/var/lib/snapd/desktop/icons",
+];
 
-/// Look in $HOME/.icons (for backwards compatibility), in $XDG_DATA_DIRS/icons, in $XDG_DATA_DIRS/pixmaps and in /usr/share/pixmaps (in that order).
-/// Paths that are not found are filtered out.
-fn icon_theme_base_paths() -> Vec<PathBuf> {
+/// Look in $HOME/.icons (for backwards compatibility), in $XDG_DATA_HOME/icons, in $XDG_DATA_DIRS/icons,
+/// and then in $XDG_DATA_HOME/pixmaps and $XDG_DATA_DIRS/pixmaps, which includes /usr/share/pixmaps (in that order).
+/// With `app_exports`, also look in the icon directories exported by Flatpak and Snap applications,
+/// which are not always part of $XDG_DATA_DIRS, after $XDG_DATA_DIRS/icons.
+/// With `legacy_order`, look in $XDG_DATA_DIRS first and in $HOME/.icons last instead.
+/// Paths that are not found are kept, the registry filters them out.
+pub(crate) fn base_path_candidates(app_exports: bool, legacy_order: bool) -> Vec<PathBuf> {
     let base_dirs = BaseDirectories::new();
 
-    let data_dirs = base_dirs
-        .get_data_dirs()
-        .into_iter()
-        .flat_map(|p| [p.join("icons"), p.join("pixmaps")]);
-
-    let data_home_dirs = base_dirs
-        .get_data_home()
-        .into_iter()
-        .flat_map(|data_home| [data_home.join("icons"), data_home.join("pixmaps")].into_iter());
-
-    let home_dir = std::env::home_dir()
-        .into_iter()
-        .map(|home| home.join(".icons"));
-
-    data_dirs
-        .chain(data_home_dirs)
-        .chain(home_dir)
-        .filter(|p| p.exists())
-        .collect()
+    ordered_base_paths(
+        std::env::home_dir(),
+        base_dirs.get_data_home(),
+        base_dirs.get_data_dirs(),
+        app_exports,
+        legacy_order,
+    )
+}
+
+fn ordered_base_paths(
+    home: Option<PathBuf>,
+    data_home: Option<PathBuf>,
+    data_dirs: Vec<PathBuf>,
+    app_exports: bool,
+    legacy_order: bool,
+) -> Vec<PathBuf> {
+    let home_dir = home.map(|home| home.join(".icons"));
+
+    let user_app_exports = data_home
+        .as_ref()
+        .filter(|_| app_exports)
+        .map(|data_home| data_home.join("flatpak/exports/share/icons"));
+
+    let app_exports = user_app_exports.into_iter().chain(
+        SYSTEM_APP_EXPORTS
+            .into_iter()
+            .filter(|_| app_exports)
+            .map(PathBuf::from),
+    );
+
+    let data_dirs = |dir| data_dirs.iter().map(move |p| p.join(dir));
+    let data_home_dirs = |dir| data_home.iter().map(move |p| p.join(dir));
+
+    if legacy_order {
+        let data_dirs = data_dirs("icons")
+            .zip(data_dirs("pixmaps"))
+            .flat_map(|(icons, pixmaps)| [icons, pixmaps]);
+        let data_home_dirs = data_home_dirs("icons")
+            .zip(data_home_dirs("pixmaps"))
+            .flat_map(|(icons, pixmaps)| [icons, pixmaps]);
+
+        data_dirs
+            .chain(data_home_dirs)
+            .chain(app_exports)
+            .chain(home_dir)
+            .collect()
+    } else {
+        home_dir
+            .into_iter()
+            .chain(data_home_dirs("icons"))
+            .chain(data_dirs("icons"))
+            .chain(app_exports)
+            .chain(data_home_dirs("pixmaps"))
+            .chain(data_dirs("pixmaps"))
+            .collect()
+    }
+}
+
+/// The base paths of the default system data directories, without the user directories.
+pub(crate) fn system_base_path_candidates(app_exports: bool, legacy_order: bool) -> Vec<PathBuf> {
+    let data_dirs = vec![
+        PathBuf::from("/usr/local/share"),
+        PathBuf::from("/usr/share"),
+    ];
+    ordered_base_paths(None, None, data_dirs, app_exports, legacy_order)
 }
 
 #[derive(Clone, Debug)]
@@ -35,25 +88,68 @@ pub struct ThemePath(pub PathBuf);
 
 #[cfg(test)]
 mod test {
-    use crate::theme::paths::icon_theme_base_paths;
+    use crate::IconThemeRegistry;
+    use crate::theme::paths::ordered_base_paths;
     use crate::theme::{Theme, get_all_themes};
     use speculoos::prelude::*;
+    use std::path::PathBuf;
 
     #[test]
     fn should_get_all_themes() {
-        let themes = get_all_themes();
+        let themes = get_all_themes(&IconThemeRegistry::global().base_paths());
         assert_that!(themes.get(&b"hicolor"[..])).is_some();
     }
 
     #[test]
     fn should_get_theme_paths_ordered() {
-        let base_paths = icon_theme_base_paths();
+        let base_paths = IconThemeRegistry::global().base_paths();
         assert_that!(base_paths).is_not_empty()
     }
 
+    #[test]
+    fn should_order_base_paths_like_the_specification() {
+        let paths = |legacy_order| {
+            ordered_base_paths(
+                Some("/home/user".into()),
+                Some("/home/user/.local/share".into()),
+                vec!["/usr/local/share".into(), "/usr/share".into()],
+                false,
+                legacy_order,
+            )
+        };
+
+        assert_eq!(
+            paths(false),
+            [
+                "/home/user/.icons",
+                "/home/user/.local/share/icons",
+                "/usr/local/share/icons",
+                "/usr/share/icons",
+                "/home/user/.local/share/pixmaps",
+                "/usr/local/share/pixmaps",
+                "/usr/share/pixmaps",
+            ]
+            .map(PathBuf::from)
+        );
+
+        assert_eq!(
+            paths(true),
+            [
+                "/usr/local/share/icons",
+                "/usr/local/share/pixmaps",
+                "/usr/share/icons",
+                "/usr/share/pixmaps",
+                "/home/user/.local/share/icons",
+                "/home/user/.local/share/pixmaps",
+                "/home/user/.icons",
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
     #[test]
     fn should_read_theme_index() {
-        let themes = get_all_themes();
+        let themes = get_all_themes(&IconThemeRegistry::global().base_paths());
         let themes: Vec<&Theme> = themes.values().flatten().collect();
         assert_that!(themes).is_not_empty();
     }
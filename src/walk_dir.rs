@@ -10,7 +10,7 @@ use std::{
     path::PathBuf,
 };
 
-const MAX_DEPTH: usize = 5;
+pub(crate) const MAX_DEPTH: usize = 5;
 
 pub struct Iter {
     directories_to_walk: VecDeque<(PathBuf, usize)>,
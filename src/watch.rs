@@ -0,0 +1,679 @@
+//! Automatic invalidation of the registry and the lookup cache with inotify.
+
+use crate::theme::gtk_cache;
+use crate::{IconCache, IconThemeRegistry};
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+
+// Icon directories are nested at most twice in a theme, as in `48x48/apps` or `apps/48`.
+const MAX_DEPTH: u8 = 2;
+
+/// A change of the installed icon themes, reported by an [`IconThemeWatcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IconThemeChange {
+    /// A theme was installed, removed or modified, and the registry was reloaded.
+    Themes,
+    /// An icon file was added or removed, and the cached lookups it may affect were forgotten.
+    Icon(PathBuf),
+}
+
+type Callback = Box<dyn Fn(&IconThemeChange) + Send>;
+
+/// Watch the base directories and the theme directories of a registry with inotify.
+///
+/// When a theme is installed or removed, the registry is reloaded, which also clears its
+/// cache. When an icon file is added or removed in a theme, the cached lookups of the icon
+/// names it may satisfy are forgotten, the
+/// [persistent cache](crate::RegistryBuilder::with_persistent_cache) and the `icon-theme.cache`
+/// of the theme are checked again, and the [file index](crate::RegistryBuilder::with_file_index)
+/// reads its directory again. A replaced `icon-theme.cache` is opened again. Base
+/// directories which do not exist yet are watched once the registry was reloaded.
+///
+/// Caches given to lookups with [`with_icon_cache`](crate::LookupBuilder::with_icon_cache)
+/// are only updated when given to the watcher with [`with_icon_cache`](Self::with_icon_cache).
+///
+/// ## Example
+/// ```rust,no_run
+/// # fn main() -> std::io::Result<()> {
+/// use cosmic_freedesktop_icons::IconThemeWatcher;
+///
+/// // Watch until the handle is dropped.
+/// let watch = IconThemeWatcher::new()
+///     .on_change(|change| println!("{change:?}"))
+///     .spawn()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct IconThemeWatcher {
+    registry: Registry,
+    // The caches of the application, besides the cache of the registry.
+    caches: Vec<Arc<IconCache>>,
+    on_change: Option<Callback>,
+}
+
+/// A running [`IconThemeWatcher`], which stops watching when dropped.
+pub struct WatchHandle {
+    stop: Option<OwnedFd>,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum Registry {
+    Global,
+    Shared(Arc<IconThemeRegistry>),
+}
+
+impl std::ops::Deref for Registry {
+    type Target = IconThemeRegistry;
+
+    fn deref(&self) -> &IconThemeRegistry {
+        match self {
+            Registry::Global => IconThemeRegistry::global(),
+            Registry::Shared(registry) => registry,
+        }
+    }
+}
+
+impl IconThemeWatcher {
+    /// Watch the themes of the global registry.
+    pub fn new() -> Self {
+        Self {
+            registry: Registry::Global,
+            caches: Vec::new(),
+            on_change: None,
+        }
+    }
+
+    /// Watch the themes of the given registry instead of the global one.
+    pub fn with_registry(mut self, registry: Arc<IconThemeRegistry>) -> Self {
+        self.registry = Registry::Shared(registry);
+        self
+    }
+
+    /// Also update the given cache, owned by the application, along with the cache of the registry.
+    pub fn with_icon_cache(mut self, cache: Arc<IconCache>) -> Self {
+        self.caches.push(cache);
+        self
+    }
+
+    /// Call the given function after each change, once the registry and its cache were updated.
+    pub fn on_change(mut self, on_change: impl Fn(&IconThemeChange) + Send + 'static) -> Self {
+        self.on_change = Some(Box::new(on_change));
+        self
+    }
+
+    /// Start watching in a background thread.
+    pub fn spawn(self) -> io::Result<WatchHandle> {
+        let mut watches = Watches {
+            inotify: Inotify::new()?,
+            dirs: HashMap::new(),
+        };
+        watches.sync(&self.registry);
+
+        let (stop_receiver, stop) = pipe()?;
+        let thread = std::thread::Builder::new()
+            .name("icon-theme-watcher".to_owned())
+            .spawn(move || self.run(watches, stop_receiver))?;
+
+        Ok(WatchHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    fn run(self, mut watches: Watches, stop: OwnedFd) {
+        let mut buffer = vec![0; 16 * 1024];
+
+        loop {
+            match wait(&watches.inotify, &stop) {
+                Ok(true) => (),
+                Ok(false) => return,
+                Err(why) => {
+                    tracing::error!(?why, "unable to wait for icon theme changes");
+                    return;
+                }
+            }
+
+            let mut changes = Changes::default();
+            if let Err(why) = watches.read_events(&mut buffer, &mut changes) {
+                tracing::error!(?why, "unable to read icon theme changes");
+                return;
+            }
+
+            if changes.themes {
+                self.registry.reload();
+                for cache in &self.caches {
+                    cache.clear();
+                }
+                watches.sync(&self.registry);
+                self.notify(IconThemeChange::Themes);
+                continue;
+            }
+
+            for theme_dir in changes.gtk_caches {
+                self.registry.forget_gtk_cache(&theme_dir);
+            }
+
+            for path in changes.icons {
+                self.registry.forget_icon_file(&path);
+                if let Some(name) = icon_name(&path) {
+                    let caches = self.caches.iter().map(Arc::as_ref);
+                    for cache in std::iter::once(&self.registry.cache).chain(caches) {
+                        cache.remove_icons(|cached| is_related(cached, name));
+                    }
+                }
+                self.notify(IconThemeChange::Icon(path));
+            }
+        }
+    }
+
+    fn notify(&self, change: IconThemeChange) {
+        if let Some(on_change) = &self.on_change {
+            on_change(&change);
+        }
+    }
+}
+
+impl Default for IconThemeWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        // Closing the pipe wakes the watcher thread up.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Base,
+    Theme { depth: u8 },
+}
+
+// The watched directories, by watch descriptor.
+struct Watches {
+    inotify: Inotify,
+    dirs: HashMap<i32, (PathBuf, Kind)>,
+}
+
+#[derive(Default)]
+struct Changes {
+    themes: bool,
+    icons: Vec<PathBuf>,
+    // The theme directories whose `icon-theme.cache` was replaced.
+    gtk_caches: Vec<PathBuf>,
+}
+
+impl Watches {
+    // Watch the base paths and the themes of the registry.
+    fn sync(&mut self, registry: &IconThemeRegistry) {
+        let themes = registry.snapshot();
+        for path in &themes.base_paths {
+            self.add(path, Kind::Base);
+        }
+
+        for theme in themes.themes.values().flatten() {
+            self.add_theme_dir(&theme.path.0, 0);
+        }
+    }
+
+    fn add(&mut self, path: &Path, kind: Kind) {
+        match self.inotify.add_watch(path) {
+            Ok(wd) => {
+                self.dirs.insert(wd, (path.to_owned(), kind));
+            }
+            Err(why) => tracing::warn!(?why, ?path, "unable to watch icon directory"),
+        }
+    }
+
+    // Watch a theme directory and its icon directories.
+    fn add_theme_dir(&mut self, path: &Path, depth: u8) {
+        self.add(path, Kind::Theme { depth });
+        if depth == MAX_DEPTH {
+            return;
+        }
+
+        let Ok(entries) = path.read_dir() else {
+            return;
+        };
+
+        for entry in entries.filter_map(io::Result::ok) {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                self.add_theme_dir(&entry.path(), depth + 1);
+            }
+        }
+    }
+
+    // Read the pending events, until none is left.
+    fn read_events(&mut self, buffer: &mut [u8], changes: &mut Changes) -> io::Result<()> {
+        loop {
+            let len = match self.inotify.read(buffer) {
+                Ok(len) => len,
+                Err(why) if why.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(why) => return Err(why),
+            };
+
+            for event in events(&buffer[..len]) {
+                self.handle(event, changes);
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event, changes: &mut Changes) {
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            // Events were lost, reload everything.
+            changes.themes = true;
+            return;
+        }
+
+        if event.mask & libc::IN_IGNORED != 0 {
+            self.dirs.remove(&event.wd);
+            return;
+        }
+
+        let Some(&(ref dir, kind)) = self.dirs.get(&event.wd) else {
+            return;
+        };
+
+        // An event on the watched directory itself.
+        if event.name.is_empty() {
+            if matches!(kind, Kind::Base) {
+                changes.themes = true;
+            }
+            return;
+        }
+
+        let path = dir.join(event.name);
+        let is_dir = event.mask & libc::IN_ISDIR != 0;
+        match kind {
+            // A theme was installed or removed.
+            Kind::Base if is_dir => changes.themes = true,
+
+            Kind::Theme { .. } if event.name == "index.theme" => changes.themes = true,
+
+            Kind::Theme { depth: 0 } if event.name == gtk_cache::FILE_NAME => {
+                if !changes.gtk_caches.contains(dir) {
+                    changes.gtk_caches.push(dir.clone());
+                }
+            }
+
+            Kind::Theme { depth } if is_dir => {
+                if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 && depth < MAX_DEPTH {
+                    self.add_theme_dir(&path, depth + 1);
+                }
+            }
+
+            // An icon of a theme or of a pixmaps directory.
+            _ => {
+                if icon_name(&path).is_some() && !changes.icons.contains(&path) {
+                    changes.icons.push(path);
+                }
+            }
+        }
+    }
+}
+
+struct Event<'a> {
+    wd: i32,
+    mask: u32,
+    name: &'a OsStr,
+}
+
+fn events(buffer: &[u8]) -> impl Iterator<Item = Event<'_>> {
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        let header = buffer.get(offset..offset + size_of::<libc::inotify_event>())?;
+        // SAFETY: the header has the size of an event, which may not be aligned in the buffer.
+        let event =
+            unsafe { std::ptr::read_unaligned(header.as_ptr().cast::<libc::inotify_event>()) };
+
+        let start = offset + header.len();
+        offset = start + event.len as usize;
+
+        // The name is padded with null bytes.
+        let name = buffer.get(start..offset)?;
+        let name = &name[..memchr::memchr(0, name).unwrap_or(name.len())];
+
+        Some(Event {
+            wd: event.wd,
+            mask: event.mask,
+            name: OsStr::from_bytes(name),
+        })
+    })
+}
+
+// The icon name of an icon file, if it is one.
+fn icon_name(path: &Path) -> Option<&str> {
+    if !matches!(path.extension()?.to_str()?, "png" | "svg" | "xpm") {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    Some(stem.strip_suffix(".symbolic").unwrap_or(stem))
+}
+
+// Whether a cached lookup may be satisfied by the given icon name. This is the case when
+// one of the looked up names is the icon name, a generic fallback of the icon name, or
+// when the icon is one of its `-symbolic` or directional variants.
+fn is_related(cache_name: &str, icon_name: &str) -> bool {
+    let is_prefix = |prefix: &str, name: &str| {
+        name.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+    };
+
+    cache_name
+        .split('/')
+        .any(|name| is_prefix(name, icon_name) || is_prefix(icon_name, name))
+}
+
+struct Inotify(OwnedFd);
+
+impl Inotify {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the file descriptor was just created and is not owned by anything else.
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    fn add_watch(&self, path: &Path) -> io::Result<i32> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.0.as_raw_fd(), path.as_ptr(), MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(wd)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let len =
+            unsafe { libc::read(self.0.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(len as usize)
+    }
+}
+
+// A pipe whose write end is closed to stop the watcher thread.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: both file descriptors were just created and are not owned by anything else.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+// Wait for inotify events, returns `false` when the watcher should stop.
+fn wait(inotify: &Inotify, stop: &OwnedFd) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd {
+            fd: inotify.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: stop.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok(fds[1].revents == 0);
+        }
+
+        let why = io::Error::last_os_error();
+        if why.kind() != io::ErrorKind::Interrupted {
+            return Err(why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Changes, Inotify, Kind, Watches, is_related};
+    use crate::temp_dir::{TempDir, write_theme};
+    use crate::{IconCache, IconThemeChange, IconThemeRegistry, IconThemeWatcher, lookup};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn should_relate_icon_names_to_cached_lookups() {
+        assert!(is_related("edit-delete", "edit-delete-symbolic"));
+        assert!(is_related(
+            "network-wireless-signal-good",
+            "network-wireless"
+        ));
+        assert!(is_related("org.gnome.Nautilus/folder", "folder"));
+        assert!(!is_related("folder", "folder2"));
+        assert!(!is_related("edit-delete", "edit-copy"));
+    }
+
+    #[test]
+    fn should_report_icon_changes() {
+        let theme = TempDir::new("watch");
+        let apps = theme.join("48x48/apps");
+        std::fs::create_dir_all(&apps).unwrap();
+
+        let mut watches = Watches {
+            inotify: Inotify::new().unwrap(),
+            dirs: HashMap::new(),
+        };
+        watches.add_theme_dir(&theme, 0);
+
+        std::fs::write(apps.join("firefox.png"), b"").unwrap();
+        std::fs::write(apps.join("README"), b"").unwrap();
+        std::fs::create_dir(theme.join("scalable")).unwrap();
+        std::fs::write(theme.join("index.theme"), b"[Icon Theme]\n").unwrap();
+        std::fs::write(theme.join("icon-theme.cache"), b"").unwrap();
+
+        let mut changes = Changes::default();
+        watches
+            .read_events(&mut vec![0; 4096], &mut changes)
+            .unwrap();
+
+        assert_eq!(changes.icons, [apps.join("firefox.png")]);
+        assert_eq!(changes.gtk_caches, [theme.to_path_buf()]);
+        assert!(changes.themes);
+        assert!(
+            watches
+                .dirs
+                .values()
+                .any(|(path, kind)| path.ends_with("scalable")
+                    && matches!(kind, Kind::Theme { depth: 1 }))
+        );
+    }
+
+    #[test]
+    fn should_update_caches_of_the_application() {
+        let root = TempDir::new("watch-caches");
+        let apps = root.join("Fixture/16x16/apps");
+        write_theme(&root.join("Fixture"), "Fixture");
+
+        let registry = Arc::new(
+            IconThemeRegistry::builder()
+                .with_base_paths([&*root])
+                .build(),
+        );
+        let cache = Arc::new(IconCache::new());
+        let find = || {
+            lookup("app")
+                .with_registry(&registry)
+                .with_icon_cache(&cache)
+                .with_theme("Fixture")
+                .with_size(16)
+                .find()
+        };
+        assert_eq!(find(), None);
+        assert_eq!(cache.len(), 1);
+
+        let (sender, changes) = std::sync::mpsc::channel();
+        let _watch = IconThemeWatcher::new()
+            .with_registry(registry.clone())
+            .with_icon_cache(cache.clone())
+            .on_change(move |change| _ = sender.send(change.clone()))
+            .spawn()
+            .unwrap();
+        std::fs::write(apps.join("app.png"), b"").unwrap();
+
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)),
+            Ok(IconThemeChange::Icon(apps.join("app.png")))
+        );
+        assert!(cache.is_empty());
+        assert_eq!(find(), Some(apps.join("app.png")));
+    }
+
+    #[test]
+    fn should_update_file_index() {
+        let root = TempDir::new("watch-file-index");
+        let apps = root.join("Fixture/16x16/apps");
+        write_theme(&root.join("Fixture"), "Fixture");
+
+        let registry = Arc::new(
+            IconThemeRegistry::builder()
+                .with_base_paths([&*root])
+                .with_file_index()
+                .with_revalidation_interval(Duration::from_secs(60))
+                .build(),
+        );
+        let find = || {
+            lookup("app")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .find()
+        };
+        assert_eq!(find(), None);
+
+        let (sender, changes) = std::sync::mpsc::channel();
+        let _watch = IconThemeWatcher::new()
+            .with_registry(registry.clone())
+            .on_change(move |change| _ = sender.send(change.clone()))
+            .spawn()
+            .unwrap();
+        std::fs::write(apps.join("app.png"), b"").unwrap();
+
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)),
+            Ok(IconThemeChange::Icon(apps.join("app.png")))
+        );
+        assert_eq!(find(), Some(apps.join("app.png")));
+    }
+
+    #[test]
+    fn should_update_persistent_cache() {
+        let root = TempDir::new("watch-persistent-cache");
+        let apps = root.join("icons/Fixture/16x16/apps");
+        write_theme(&root.join("icons/Fixture"), "Fixture");
+
+        let registry = Arc::new(
+            IconThemeRegistry::builder()
+                .with_base_paths([root.join("icons")])
+                .with_persistent_cache_dir(root.join("cache"))
+                .with_revalidation_interval(Duration::from_secs(60))
+                .build(),
+        );
+        let find = || {
+            lookup("app")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .with_cache()
+                .find()
+        };
+        assert_eq!(find(), None);
+
+        let (sender, changes) = std::sync::mpsc::channel();
+        let _watch = IconThemeWatcher::new()
+            .with_registry(registry.clone())
+            .on_change(move |change| _ = sender.send(change.clone()))
+            .spawn()
+            .unwrap();
+        std::fs::write(apps.join("app.png"), b"").unwrap();
+
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)),
+            Ok(IconThemeChange::Icon(apps.join("app.png")))
+        );
+        assert_eq!(find(), Some(apps.join("app.png")));
+    }
+
+    #[test]
+    fn should_check_gtk_cache_again() {
+        let root = TempDir::new("watch-gtk-cache");
+        let apps = root.join("Fixture/16x16/apps");
+        write_theme(&root.join("Fixture"), "Fixture");
+        crate::write_icon_theme_cache(root.join("Fixture")).unwrap();
+
+        let registry = Arc::new(
+            IconThemeRegistry::builder()
+                .with_base_paths([&*root])
+                .with_revalidation_interval(Duration::from_secs(60))
+                .build(),
+        );
+        let find = || {
+            lookup("app")
+                .with_registry(&registry)
+                .with_theme("Fixture")
+                .with_size(16)
+                .find()
+        };
+        assert_eq!(find(), None);
+
+        let (sender, changes) = std::sync::mpsc::channel();
+        let _watch = IconThemeWatcher::new()
+            .with_registry(registry.clone())
+            .on_change(move |change| _ = sender.send(change.clone()))
+            .spawn()
+            .unwrap();
+        std::fs::write(apps.join("app.png"), b"").unwrap();
+
+        assert_eq!(
+            changes.recv_timeout(Duration::from_secs(5)),
+            Ok(IconThemeChange::Icon(apps.join("app.png")))
+        );
+        assert_eq!(find(), Some(apps.join("app.png")));
+    }
+
+    #[test]
+    fn should_stop_watching_on_drop() {
+        let registry = Arc::new(IconThemeRegistry::new());
+        let watch = IconThemeWatcher::new()
+            .with_registry(registry)
+            .spawn()
+            .unwrap();
+
+        drop(watch);
+    }
+}